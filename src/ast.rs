@@ -1,46 +1,702 @@
-use std::collections::HashMap;
+use std::fmt;
 
-#[derive(Debug)]
+use crate::diagnostics::SourceSpan;
+use crate::metadata::{Form, Key, Tempo, TimeSignature};
+
+/// Where a node came from in the source. Without the `spans` feature this is
+/// a zero-sized placeholder, so the AST carries no location data.
+#[cfg(feature = "spans")]
+pub type Span = SourceSpan;
+#[cfg(not(feature = "spans"))]
+pub type Span = NoSpan;
+
+/// Stand-in for [`SourceSpan`] when the `spans` feature is disabled.
+#[cfg(not(feature = "spans"))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoSpan;
+
+#[cfg(not(feature = "spans"))]
+impl NoSpan {
+    pub fn from_pest_span(_span: pest::Span<'_>) -> Self {
+        NoSpan
+    }
+
+    pub fn as_source_span(&self) -> Option<SourceSpan> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Song {
-    pub directives: HashMap<String, String>,
+    pub directives: Metadata,
     pub blocks: Vec<Block>,
+    /// Comments in source order; only filled in when parsing with
+    /// `keep_comments`, otherwise they are discarded.
+    pub comments: Vec<Comment>,
+    pub span: Span,
+}
+
+impl Song {
+    /// Numbers the bars as written, from 1. Pickups get no number, and the
+    /// bars of a `#Chorus*` reference are counted where it is played. A
+    /// repeat is numbered once, as on a printed chart; number the song from
+    /// [`crate::form::expand_form`] to count every pass.
+    pub fn numbered_measures(&self) -> Vec<NumberedMeasure<'_>> {
+        let mut measures = Vec::new();
+        let mut number = 0;
+        for (block_index, block) in self.blocks.iter().enumerate() {
+            for (line_index, line) in block.lines.iter().enumerate() {
+                for (segment_index, segment) in line.segments.iter().enumerate() {
+                    let Segment::Measure(measure) = segment else {
+                        continue;
+                    };
+                    if measure.pickup {
+                        continue;
+                    }
+                    number += 1;
+                    measures.push(NumberedMeasure {
+                        number,
+                        block: block_index,
+                        line: line_index,
+                        segment: segment_index,
+                        measure,
+                    });
+                }
+            }
+        }
+        measures
+    }
+
+    /// The bar with the given number, for starting "from bar 33".
+    pub fn measure_at(&self, number: u32) -> Option<NumberedMeasure<'_>> {
+        self.numbered_measures().into_iter().find(|m| m.number == number)
+    }
+
+    /// A rehearsal mark for each section: its own `@mark`, or else the next
+    /// letter from A. A `@mark` that is itself a letter carries on the
+    /// lettering from there, so `@mark: F` is followed by G.
+    pub fn rehearsal_marks(&self) -> Vec<String> {
+        let mut next = 0;
+        self.blocks
+            .iter()
+            .map(|block| match block.mark() {
+                Some(mark) => {
+                    if let Some(index) = rehearsal_index(mark) {
+                        next = index + 1;
+                    }
+                    mark.to_string()
+                }
+                None => {
+                    next += 1;
+                    rehearsal_letter(next - 1)
+                }
+            })
+            .collect()
+    }
+}
+
+/// A, B, ... Z, then AA, BB and so on.
+fn rehearsal_letter(index: usize) -> String {
+    let letter = char::from(b'A' + (index % 26) as u8);
+    std::iter::repeat_n(letter, index / 26 + 1).collect()
 }
-#[derive(Debug)]
+
+/// Where `mark` falls in the lettering, if it is a rehearsal letter.
+fn rehearsal_index(mark: &str) -> Option<usize> {
+    let letter = mark.chars().next().filter(char::is_ascii_uppercase)?;
+    if !mark.chars().all(|c| c == letter) {
+        return None;
+    }
+    Some((mark.len() - 1) * 26 + usize::from(letter as u8 - b'A'))
+}
+
+/// A bar of a song and where to find it: indexes into [`Song::blocks`],
+/// [`Block::lines`] and [`LyricLine::segments`].
+#[derive(Debug, Clone, Copy)]
+pub struct NumberedMeasure<'a> {
+    pub number: u32,
+    pub block: usize,
+    pub line: usize,
+    pub segment: usize,
+    pub measure: &'a Measure,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentKind {
+    /// `// to the end of the line`
+    Line,
+    /// `/* anywhere, across lines */`
+    Block,
+}
+
+/// A comment with its delimiters stripped.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub kind: CommentKind,
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
 pub struct Directive {
     pub name: String,
     pub value: String,
+    /// `None` for a directive the source did not have, such as the `@key` a
+    /// transposition writes.
+    pub span: Option<Span>,
+}
+
+/// Directives that only make sense once per song; later copies are ignored.
+pub const SINGLE_VALUED_DIRECTIVES: [&str; 6] = ["title", "key", "time", "tempo", "capo", "form"];
+
+/// Single-valued directives that can also change partway through a song.
+pub const CHANGEABLE_DIRECTIVES: [&str; 3] = ["key", "time", "tempo"];
+
+/// A song's directives in source order. A name may appear more than once,
+/// e.g. one `@composer:` per writer.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    directives: Vec<Directive>,
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The first value given for `name`.
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.directives
+            .iter()
+            .find(|d| d.name == name)
+            .map(|d| &d.value)
+    }
+
+    /// Every value given for `name`, in source order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.directives
+            .iter()
+            .filter(move |d| d.name == name)
+            .map(|d| d.value.as_str())
+    }
+
+    /// The song's `@key`, if present and valid.
+    pub fn key(&self) -> Option<Key> {
+        self.get("key")?.parse().ok()
+    }
+
+    /// The song's `@time`, if present and valid.
+    pub fn time(&self) -> Option<TimeSignature> {
+        self.get("time")?.parse().ok()
+    }
+
+    /// The song's `@tempo`, if present and valid.
+    pub fn tempo(&self) -> Option<Tempo> {
+        self.get("tempo")?.parse().ok()
+    }
+
+    /// The song's `@form`, if present and valid.
+    pub fn form(&self) -> Option<Form> {
+        self.get("form")?.parse().ok()
+    }
+
+    /// The song's `@capo` fret, if present and a whole number.
+    pub fn capo(&self) -> Option<usize> {
+        self.get("capo")?.trim().parse().ok()
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.directives.iter().any(|d| d.name == name)
+    }
+
+    /// Appends a directive after the existing ones.
+    pub fn push(&mut self, directive: Directive) {
+        self.directives.push(directive);
+    }
+
+    /// Sets `name` to a single value, keeping the position of its first
+    /// occurrence, or appends it if the song does not have it yet.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        let value = value.into();
+        match self.directives.iter().position(|d| d.name == name) {
+            Some(index) => {
+                self.directives[index].value = value;
+                let mut seen = false;
+                self.directives.retain(|d| {
+                    if d.name != name {
+                        return true;
+                    }
+                    let keep = !seen;
+                    seen = true;
+                    keep
+                });
+            }
+            None => self.directives.push(Directive { name, value, span: None }),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Directive> {
+        self.directives.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.directives.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.directives.is_empty()
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Block {
+    /// The header without its `#`, with runs of whitespace collapsed, e.g.
+    /// `Verse 2` or `Intro (x2)`.
     pub section_name: String,
+    pub section: SectionName,
     pub lines: Vec<LyricLine>,
+    /// Directives written between the lines, in source order.
+    pub directives: Vec<InlineDirective>,
+    /// Written as `#Chorus*`: the lines are those of the earlier section
+    /// with the same name.
+    pub is_reference: bool,
+    pub span: Span,
+}
+
+impl Block {
+    /// The section name as printed in its header, e.g. `Verse 1`.
+    pub fn name(&self) -> &str {
+        &self.section_name
+    }
+
+    /// Whether `name`, as written in a `@form`, refers to this section.
+    /// Case and spacing are ignored, and the repeat count can be left off, so
+    /// `Intro` matches `#Intro (x2)`.
+    pub fn is_named(&self, name: &str) -> bool {
+        let name = name.split_whitespace().collect::<Vec<&str>>().join(" ").to_lowercase();
+        self.section_name.to_lowercase() == name || self.section.title().to_lowercase() == name
+    }
+
+    /// The section's rehearsal mark, from an `@mark: A` written in it.
+    pub fn mark(&self) -> Option<&str> {
+        self.directives
+            .iter()
+            .find(|d| d.directive.name == "mark")
+            .map(|d| d.directive.value.as_str())
+    }
+
+    /// The directives that take effect just before `lines[line]`; passing
+    /// `lines.len()` gives those written after the last line.
+    pub fn directives_at(&self, line: usize) -> impl Iterator<Item = &Directive> {
+        self.directives
+            .iter()
+            .filter(move |d| d.line == line)
+            .map(|d| &d.directive)
+    }
+}
+
+/// A directive inside a section, such as `@key: D Major` before the last
+/// chorus or `@time: 3/4` for one bar. It changes the song from `line`
+/// onwards, an index into [`Block::lines`].
+#[derive(Debug, Clone)]
+pub struct InlineDirective {
+    pub directive: Directive,
+    pub line: usize,
+}
+
+/// The parts of a section header. `#Verse 2 (x2)` has the label `Verse`, the
+/// number 2 and is played twice; `#Pre-Chorus` only has a label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionName {
+    pub label: String,
+    pub number: Option<u32>,
+    pub repeat_count: Option<u32>,
+}
+
+impl SectionName {
+    /// The label and number without the repeat count, e.g. `Verse 2`.
+    pub fn title(&self) -> String {
+        match self.number {
+            Some(number) => format!("{} {}", self.label, number),
+            None => self.label.clone(),
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LyricLine {
     pub segments: Vec<Segment>,
+    /// Written as `{Pickup} And [C]soon`: the words before the first chord
+    /// lead into the bar instead of starting one.
+    pub pickup: bool,
+    pub span: Span,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Segment {
-    Measure(Vec<ChordOrText>),
-    Inline(Vec<ChordOrText>),
+    Measure(Measure),
+    Inline(Vec<ChordOrText>, Span),
+}
+
+impl Segment {
+    pub fn span(&self) -> Span {
+        match self {
+            Segment::Measure(measure) => measure.span,
+            Segment::Inline(_, span) => *span,
+        }
+    }
+
+    /// The chords and lyrics in this segment, without any barlines.
+    pub fn items(&self) -> &[ChordOrText] {
+        match self {
+            Segment::Measure(measure) => &measure.items,
+            Segment::Inline(items, _) => items,
+        }
+    }
+}
+
+/// One bar of a chart, e.g. `|: [C] [G] :| x3`.
+#[derive(Debug, Clone)]
+pub struct Measure {
+    pub start: Barline,
+    /// The ending this measure starts, e.g. `|2. [G] |`.
+    pub volta: Option<Volta>,
+    /// Written as `| {Pickup} [G] |`: an incomplete bar leading into the
+    /// first full one, which is not counted as a bar of its own.
+    pub pickup: bool,
+    pub items: Vec<ChordOrText>,
+    /// `None` when the measure is never closed. A barline shared with the
+    /// next measure closes this one and opens that one.
+    pub end: Option<Barline>,
+    /// How many times a repeat ending here is played, from `x3`; `None`
+    /// means the usual twice.
+    pub repeat_count: Option<u32>,
+    pub span: Span,
+}
+
+/// When one item of a measure is played: `index` into [`Measure::items`],
+/// the beat it starts on, counting from 1, and how many beats it lasts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BeatPosition {
+    pub index: usize,
+    pub beat: u32,
+    pub length: u32,
+}
+
+impl Measure {
+    /// Lays the measure's chords, `N.C.`s and rests out over a bar of `time`.
+    /// When no lengths are written the bar is shared evenly, earlier chords
+    /// taking any beat left over. Once a measure gives a `[C:2]`, `[C]..` or
+    /// `/`, every other chord counts one beat, and a `/` adds a beat to the
+    /// item before it. A `%` fills the bar. A pickup is not shared out: each
+    /// chord counts one beat unless written longer, and together they fill
+    /// the end of the bar, so `| {Pickup} [G] |` in 4/4 plays G on beat 4.
+    pub fn beats(&self, time: &TimeSignature) -> Vec<BeatPosition> {
+        let mut positions: Vec<BeatPosition> = Vec::new();
+        if self.has_written_beats() || self.pickup {
+            for (index, item) in self.items.iter().enumerate() {
+                let length = match item {
+                    ChordOrText::Chord(chord) => chord.duration.unwrap_or(1),
+                    ChordOrText::NoChord(_) | ChordOrText::Rest(_) => 1,
+                    ChordOrText::RepeatMeasure(_) => time.beats,
+                    ChordOrText::Slash(_) => {
+                        match positions.last_mut() {
+                            Some(previous) => previous.length = previous.length.saturating_add(1),
+                            // A bar that starts with `/` carries the last chord over.
+                            None => positions.push(BeatPosition { index, beat: 0, length: 1 }),
+                        }
+                        continue;
+                    }
+                    ChordOrText::Text(..) | ChordOrText::Marker(..) => continue,
+                };
+                positions.push(BeatPosition { index, beat: 0, length });
+            }
+        } else {
+            let timed: Vec<usize> = self
+                .items
+                .iter()
+                .enumerate()
+                .filter(|(_, item)| matches!(item,
+                    ChordOrText::Chord(_) | ChordOrText::NoChord(_) | ChordOrText::Rest(_) | ChordOrText::RepeatMeasure(_)))
+                .map(|(index, _)| index)
+                .collect();
+            let count = timed.len() as u32;
+            for (n, index) in (0..).zip(timed) {
+                let length = (time.beats / count + u32::from(n < time.beats % count)).max(1);
+                positions.push(BeatPosition { index, beat: 0, length });
+            }
+        }
+
+        let total = positions.iter().fold(0, |total: u32, p| total.saturating_add(p.length));
+        let mut beat = if self.pickup { time.beats.saturating_sub(total) + 1 } else { 1 };
+        for position in &mut positions {
+            position.beat = beat;
+            beat = beat.saturating_add(position.length);
+        }
+        positions
+    }
+
+    /// Whether any item of the measure says how long it lasts.
+    pub fn has_written_beats(&self) -> bool {
+        self.items.iter().any(|item| match item {
+            ChordOrText::Chord(chord) => chord.duration.is_some(),
+            ChordOrText::Slash(_) => true,
+            _ => false,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Barline {
+    /// `|`
+    Single,
+    /// `||`
+    Double,
+    /// `|]`, the end of the piece.
+    Final,
+    /// `|:` or `||:`
+    StartRepeat,
+    /// `:|` or `:||`
+    EndRepeat,
+    /// `:|:` or `:||:`
+    EndStartRepeat,
+}
+
+impl Barline {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Barline::Single => "|",
+            Barline::Double => "||",
+            Barline::Final => "|]",
+            Barline::StartRepeat => "|:",
+            Barline::EndRepeat => ":|",
+            Barline::EndStartRepeat => ":|:",
+        }
+    }
+
+    pub fn starts_repeat(self) -> bool {
+        matches!(self, Barline::StartRepeat | Barline::EndStartRepeat)
+    }
+
+    pub fn ends_repeat(self) -> bool {
+        matches!(self, Barline::EndRepeat | Barline::EndStartRepeat)
+    }
+}
+
+/// A 1st/2nd-time ending; `|1,2.` covers more than one pass.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Volta {
+    pub endings: Vec<u32>,
 }
 
-#[derive(Debug)]
+impl Volta {
+    /// The ending as written in a chart, e.g. `1,2.`.
+    pub fn label(&self) -> String {
+        let endings: Vec<String> = self.endings.iter().map(u32::to_string).collect();
+        format!("{}.", endings.join(","))
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum ChordOrText {
     Chord(Chord),
-    Text(String),
+    Text(String, Span),
+    /// A road-map marker such as `{Segno}` or `{D.S. al Coda}`.
+    Marker(NavigationMarker, Span),
+    /// `N.C.`: nobody plays a chord here.
+    NoChord(Span),
+    /// `%`: play the previous measure again.
+    RepeatMeasure(Span),
+    /// `/`: one more beat of the chord before it.
+    Slash(Span),
+    /// `[rest]` or `[𝄽]`: a beat of silence.
+    Rest(Span),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+impl ChordOrText {
+    pub fn span(&self) -> Span {
+        match self {
+            ChordOrText::Chord(chord) => chord.span,
+            ChordOrText::Text(_, span) | ChordOrText::Marker(_, span) => *span,
+            ChordOrText::NoChord(span) | ChordOrText::RepeatMeasure(span)
+            | ChordOrText::Slash(span) | ChordOrText::Rest(span) => *span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NavigationMarker {
+    Segno,
+    Coda,
+    ToCoda,
+    Fine,
+    /// `D.C.`: back to the top.
+    DaCapo(JumpEnd),
+    /// `D.S.`: back to the segno.
+    DalSegno(JumpEnd),
+}
+
+/// Where playing stops after a D.C. or D.S. jump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JumpEnd {
+    /// Play on to the end.
+    End,
+    /// `al Fine`: stop at the Fine.
+    Fine,
+    /// `al Coda`: skip from "To Coda" to the coda.
+    Coda,
+}
+
+impl NavigationMarker {
+    /// The marker spelled out, e.g. `D.S. al Coda`.
+    pub fn name(self) -> &'static str {
+        match self {
+            NavigationMarker::Segno => "Segno",
+            NavigationMarker::Coda => "Coda",
+            NavigationMarker::ToCoda => "To Coda",
+            NavigationMarker::Fine => "Fine",
+            NavigationMarker::DaCapo(JumpEnd::End) => "D.C.",
+            NavigationMarker::DaCapo(JumpEnd::Fine) => "D.C. al Fine",
+            NavigationMarker::DaCapo(JumpEnd::Coda) => "D.C. al Coda",
+            NavigationMarker::DalSegno(JumpEnd::End) => "D.S.",
+            NavigationMarker::DalSegno(JumpEnd::Fine) => "D.S. al Fine",
+            NavigationMarker::DalSegno(JumpEnd::Coda) => "D.S. al Coda",
+        }
+    }
+
+    /// The marker with the segno and coda drawn as music symbols.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            NavigationMarker::Segno => "\u{1D10B}",
+            NavigationMarker::Coda => "\u{1D10C}",
+            NavigationMarker::ToCoda => "To \u{1D10C}",
+            NavigationMarker::DalSegno(JumpEnd::Coda) => "D.S. al \u{1D10C}",
+            NavigationMarker::DaCapo(JumpEnd::Coda) => "D.C. al \u{1D10C}",
+            _ => self.name(),
+        }
+    }
+}
+
+/// Spans and durations are ignored when comparing chords, so the same chord
+/// written in two places compares equal.
+#[derive(Debug, Clone, Eq)]
 pub struct Chord {
     pub root: Note,
-    pub inversion: Option<String>,         //6, 6/9
-    pub quality: Option<String>,           // "maj", "min", "dim", etc.
-    pub extensions: Vec<Option<String>>,   // "7", "9", "b5", etc.
+    pub quality: Quality,
+    /// The seventh and any ninth, eleventh or thirteenth stacked on it.
+    pub extension: Option<Extension>,
+    /// Added, altered and omitted tones, in the order they were written.
+    pub alterations: Vec<Alteration>,
     pub bass: Option<Note>,                // For slash chords
+    /// Beats written for the chord with `[C:2]` or `[C]..`; see
+    /// [`Measure::beats`] for chords without one.
+    pub duration: Option<u32>,
+    pub span: Span,
+}
+
+impl PartialEq for Chord {
+    fn eq(&self, other: &Self) -> bool {
+        self.root == other.root
+            && self.quality == other.quality
+            && self.extension == other.extension
+            && self.alterations == other.alterations
+            && self.bass == other.bass
+    }
+}
+
+/// The triad a chord is built on, or the sus or power chord that replaces it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Quality {
+    #[default]
+    Major,
+    Minor,
+    Diminished,
+    Augmented,
+    /// A diminished triad with a minor seventh, written `m7b5` or `ø`.
+    HalfDiminished,
+    /// `sus2` or `sus4`: the third replaced by the second or fourth.
+    Suspended(u8),
+    /// `5`: root and fifth only.
+    Power,
+}
+
+/// The highest tone stacked in thirds above the triad: the 7 of `C7` or the
+/// 13 of `Cmaj13`. `major_seventh` is set for `maj7` chords; a diminished
+/// chord's seventh is always diminished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extension {
+    pub degree: u8,
+    pub major_seventh: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alteration {
+    /// `6`: a major sixth on top of the triad.
+    Sixth,
+    /// `6/9`: a sixth and a ninth without the seventh.
+    SixNine,
+    /// `add9`: a tone added without the ones between it and the triad.
+    Add(u8),
+    /// `b9`, `b5`
+    Flat(u8),
+    /// `#11`, `#5`
+    Sharp(u8),
+    /// `omit3`: a chord tone left out.
+    Omit(u8),
+    /// `alt`: an altered dominant, tensions left to the player.
+    Altered,
+}
+
+impl fmt::Display for Chord {
+    /// Writes the chord in one canonical spelling: `Cm7b5` rather than `Cø`,
+    /// `Cmaj7` rather than `CΔ7`, with tensions in the order they were written.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.root)?;
+        let degree = self.extension.map(|e| e.degree);
+        let major_seventh = self.extension.is_some_and(|e| e.major_seventh);
+        match self.quality {
+            Quality::Major | Quality::Suspended(_) => {}
+            Quality::Minor | Quality::HalfDiminished => write!(f, "m")?,
+            Quality::Diminished => write!(f, "dim")?,
+            Quality::Augmented => write!(f, "aug")?,
+            Quality::Power => write!(f, "5")?,
+        }
+        if let Some(degree) = degree {
+            match (self.quality, major_seventh) {
+                (Quality::Major | Quality::Suspended(_), true) => write!(f, "maj{}", degree)?,
+                (_, true) => write!(f, "Maj{}", degree)?,
+                (_, false) => write!(f, "{}", degree)?,
+            }
+        }
+        if self.quality == Quality::HalfDiminished {
+            write!(f, "{}b5", if degree.is_some() { "" } else { "7" })?;
+        }
+        if let Quality::Suspended(tone) = self.quality {
+            write!(f, "sus{}", tone)?;
+        }
+        for alteration in &self.alterations {
+            write!(f, "{}", alteration)?;
+        }
+        if let Some(bass) = &self.bass {
+            write!(f, "/{}", bass)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Alteration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Alteration::Sixth => write!(f, "6"),
+            Alteration::SixNine => write!(f, "6/9"),
+            Alteration::Add(tone) => write!(f, "add{}", tone),
+            Alteration::Flat(tone) => write!(f, "b{}", tone),
+            Alteration::Sharp(tone) => write!(f, "#{}", tone),
+            Alteration::Omit(tone) => write!(f, "omit{}", tone),
+            Alteration::Altered => write!(f, "alt"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
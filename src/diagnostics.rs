@@ -1,3 +1,30 @@
+use std::fmt;
+use std::fmt::Write;
+use pest::error::{ErrorVariant, InputLocation};
+use pest::RuleType;
+
+/// Codes attached to diagnostics so they can be looked up and filtered.
+pub mod codes {
+    pub const SYNTAX_ERROR: &str = "E0001";
+    pub const INVALID_CHORD: &str = "E0002";
+    pub const INVALID_DIRECTIVE: &str = "E0003";
+    pub const INVALID_SECTION: &str = "E0004";
+    pub const INTERNAL_ERROR: &str = "E0005";
+    pub const TRANSPOSE_ERROR: &str = "E0006";
+    pub const MISSING_KEY: &str = "W001";
+    pub const UNRECOGNISED_KEY: &str = "W002";
+    pub const DUPLICATE_DIRECTIVE: &str = "W003";
+    pub const EMPTY_SECTION: &str = "W004";
+    pub const UNBALANCED_MEASURE: &str = "W005";
+    pub const UNKNOWN_EXTENSION: &str = "W006";
+    pub const INVALID_DIRECTIVE_VALUE: &str = "W008";
+    pub const MISPLACED_REPEAT_COUNT: &str = "W009";
+    pub const UNMATCHED_NAVIGATION: &str = "W010";
+    pub const UNKNOWN_FORM_SECTION: &str = "W011";
+    pub const BEAT_MISMATCH: &str = "W012";
+    pub const MISPLACED_DIRECTIVE: &str = "W013";
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DiagnosticSeverity {
     Error,
@@ -13,6 +40,40 @@ pub struct Diagnostic {
     pub code: Option<String>,
 }
 
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, code: &str, span: Option<SourceSpan>) -> Self {
+        Diagnostic {
+            severity: DiagnosticSeverity::Error,
+            message: message.into(),
+            span,
+            code: Some(code.to_string()),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, code: &str, span: Option<SourceSpan>) -> Self {
+        Diagnostic {
+            severity: DiagnosticSeverity::Warning,
+            message: message.into(),
+            span,
+            code: Some(code.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            DiagnosticSeverity::Error => "error",
+            DiagnosticSeverity::Warning => "warning",
+            DiagnosticSeverity::Info => "info",
+        };
+        match &self.code {
+            Some(code) => write!(f, "{}[{}]: {}", severity, code, self.message),
+            None => write!(f, "{}: {}", severity, self.message),
+        }
+    }
+}
+
 #[derive(Debug)]
 
 pub enum LeadSheetMLError<R>{
@@ -27,6 +88,20 @@ pub enum LeadSheetMLError<R>{
         rule: Option<R>,
         span: Option<SourceSpan>
     },
+    Transpose {
+        message: String,
+    },
+}
+
+impl<R: RuleType> fmt::Display for LeadSheetMLError<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeadSheetMLError::Pest(e) => write!(f, "{}", e),
+            LeadSheetMLError::Syntax { message, .. } => write!(f, "syntax error: {}", message),
+            LeadSheetMLError::Internal { message, .. } => write!(f, "internal error: {}", message),
+            LeadSheetMLError::Transpose { message } => write!(f, "{}", message),
+        }
+    }
 }
 
 pub type ParseResult<T, R> = Result<T, LeadSheetMLError<R>>;
@@ -43,6 +118,12 @@ pub fn internal_error<R>(
     }
 }
 
+pub fn transpose_error<R>(message: impl Into<String>) -> LeadSheetMLError<R> {
+    LeadSheetMLError::Transpose {
+        message: message.into(),
+    }
+}
+
 pub fn internal<T, R>(message: impl Into<String>, rule: Option<R>, span: Option<pest::Span<'_>>) -> ParseResult<T,R> {
     Err(internal_error(message, rule, span))
 }
@@ -56,7 +137,81 @@ pub fn syntax<T,R>(message: impl Into<String>, rule: Option<R>, span: Option<pes
     })
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What [`error_to_diagnostic`] needs to know about the rules of a grammar.
+pub trait DescribeRule: RuleType {
+    /// The rule in terms a chart author would recognise.
+    fn description(&self) -> &'static str;
+    /// The error code for input that failed at this rule, if it has its own.
+    fn code(&self) -> Option<&'static str>;
+    /// Whether to leave the rule out of "expected ..." messages.
+    fn is_noise(&self) -> bool;
+}
+
+/// Turns a parse or transpose failure into a [`Diagnostic`] with a friendly
+/// message, an error code and, where known, the span it points at.
+pub fn error_to_diagnostic<R: DescribeRule>(error: &LeadSheetMLError<R>, source: &str) -> Diagnostic {
+    match error {
+        LeadSheetMLError::Pest(e) => {
+            let span = match e.location {
+                InputLocation::Pos(pos) => SourceSpan { start: pos, end: pos },
+                InputLocation::Span((start, end)) => SourceSpan { start, end },
+            };
+            match &e.variant {
+                ErrorVariant::ParsingError { positives, .. } => Diagnostic::error(
+                    expected_message(positives, source, span.start),
+                    rules_code(positives),
+                    Some(span)),
+                ErrorVariant::CustomError { message } => Diagnostic::error(
+                    message.clone(),
+                    codes::SYNTAX_ERROR,
+                    Some(span)),
+            }
+        }
+        LeadSheetMLError::Syntax { message, rule, span } => Diagnostic::error(
+            message.clone(),
+            rule.map(|r| rules_code(&[r])).unwrap_or(codes::SYNTAX_ERROR),
+            *span),
+        LeadSheetMLError::Internal { message, span, .. } => Diagnostic::error(
+            format!("internal error: {}", message),
+            codes::INTERNAL_ERROR,
+            *span),
+        LeadSheetMLError::Transpose { message } => Diagnostic::error(
+            message.clone(),
+            codes::TRANSPOSE_ERROR,
+            None),
+    }
+}
+
+fn expected_message<R: DescribeRule>(positives: &[R], source: &str, position: usize) -> String {
+    let mut expected: Vec<&str> = Vec::new();
+    for rule in positives.iter().filter(|r| !r.is_noise()) {
+        let description = rule.description();
+        if !expected.contains(&description) {
+            expected.push(description);
+        }
+    }
+
+    let mut message = match expected.as_slice() {
+        [] => "unexpected input".to_string(),
+        [only] => format!("expected {}", only),
+        [rest @ .., last] => format!("expected {} or {}", rest.join(", "), last),
+    };
+    let previous = source[..position.min(source.len())]
+        .chars()
+        .rev()
+        .take_while(|c| *c != '\n')
+        .find(|c| !c.is_whitespace());
+    if let Some(previous) = previous {
+        write!(message, " after '{}'", previous).unwrap();
+    }
+    message
+}
+
+fn rules_code<R: DescribeRule>(rules: &[R]) -> &'static str {
+    rules.iter().find_map(R::code).unwrap_or(codes::SYNTAX_ERROR)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct SourceSpan{
     pub start: usize,
     pub end: usize
@@ -69,4 +224,18 @@ impl SourceSpan{
             end: span.end()
         }
     }
+
+    pub fn as_source_span(&self) -> Option<SourceSpan> {
+        Some(*self)
+    }
+
+    /// 1-based line and column (in characters) of the start of the span.
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let start = self.start.min(source.len());
+        let before = &source[..start];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let column = source[line_start..start].chars().count() + 1;
+        (line, column)
+    }
 }
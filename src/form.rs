@@ -0,0 +1,373 @@
+use crate::ast::*;
+
+/// Lays the song out in the order it is played: sections follow `@form`, or
+/// the source order without one (playing `#Intro x2` twice), repeats are
+/// written out so every pass appears once, and the road map is followed (see
+/// [`follow_road_map`]). Sections the form names but the song does not have are
+/// skipped; `parse_song_with_diagnostics` warns about them.
+pub fn expand_form(song: &Song) -> Song {
+    let sections: Vec<&Block> = match song.directives.form() {
+        Some(form) => form
+            .entries
+            .iter()
+            .flat_map(|entry| {
+                let block = song.blocks.iter().find(|b| b.is_named(&entry.section));
+                block.into_iter().flat_map(move |b| std::iter::repeat_n(b, entry.times as usize))
+            })
+            .collect(),
+        None => song
+            .blocks
+            .iter()
+            .flat_map(|b| std::iter::repeat_n(b, b.section.repeat_count.unwrap_or(1) as usize))
+            .collect(),
+    };
+    Song {
+        directives: song.directives.clone(),
+        blocks: follow_road_map(&sections),
+        comments: song.comments.clone(),
+        span: song.span,
+    }
+}
+
+/// Plays `sections` through, taking the first `{D.S.}` or `{D.C.}` back to
+/// the segno or the top. After the jump, repeats are played once with their
+/// last ending, an `al Fine` jump stops at the `{Fine}`, and an `al Coda`
+/// jump skips from `{To Coda}` to the `{Coda}`. A jump with nowhere to go is
+/// not taken. Markers take effect where they are, even partway through a
+/// lyric line. The markers themselves are dropped, like the repeat signs.
+pub fn follow_road_map(sections: &[&Block]) -> Vec<Block> {
+    let jumps = sections.iter().flat_map(|block| markers(block)).any(|marker| {
+        matches!(marker, NavigationMarker::DaCapo(_) | NavigationMarker::DalSegno(_))
+    });
+    if !jumps {
+        return sections.iter().map(|block| without_markers(&unroll_repeats(block))).collect();
+    }
+    let sections: Vec<Block> = sections.iter().map(|block| split_at_markers(block)).collect();
+    let first: Vec<Block> = sections.iter().map(unroll_repeats).collect();
+    let again: Vec<Block> = sections.iter().map(|block| play_repeats(block, true)).collect();
+
+    let mut blocks = Vec::new();
+    // The way back, once taken; later jumps back are ignored.
+    let mut jump: Option<JumpEnd> = None;
+    let mut position = Some((0, 0));
+    while let Some((section, start)) = position {
+        let block = if jump.is_some() { &again[section] } else { &first[section] };
+        let segments = block_segments(block);
+        let mut end = segments.len();
+        position = (section + 1 < sections.len()).then_some((section + 1, 0));
+        for (index, (_, segment)) in segments.iter().enumerate().skip(start) {
+            let step = match jump {
+                None => segment.items().iter().find_map(|item| match item {
+                    ChordOrText::Marker(NavigationMarker::DaCapo(until), _) => Some(Step::Jump(*until, (0, 0))),
+                    ChordOrText::Marker(NavigationMarker::DalSegno(until), _) => {
+                        find_marker(&again, NavigationMarker::Segno, (0, 0)).map(|to| Step::Jump(*until, to))
+                    }
+                    _ => None,
+                }),
+                Some(JumpEnd::Fine) if has_marker(segment, NavigationMarker::Fine) => Some(Step::Stop),
+                Some(JumpEnd::Coda) if has_marker(segment, NavigationMarker::ToCoda) => {
+                    find_marker(&again, NavigationMarker::Coda, (section, index + 1)).map(|to| Step::Jump(JumpEnd::End, to))
+                }
+                _ => None,
+            };
+            match step {
+                Some(Step::Jump(until, to)) => {
+                    jump = Some(until);
+                    position = Some(to);
+                }
+                Some(Step::Stop) => position = None,
+                None => continue,
+            }
+            end = index + 1;
+            break;
+        }
+        blocks.push(without_markers(&excerpt(block, start, end)));
+    }
+    blocks
+}
+
+/// Where the road map goes after a segment.
+enum Step {
+    /// On from a section and segment index, playing until the given end.
+    Jump(JumpEnd, (usize, usize)),
+    Stop,
+}
+
+/// Writes out the repeats in a section: `|: [C] :|` becomes `| [C] | [C] |`,
+/// each ending is only played on its pass, and `x3` counts are honoured.
+/// Repeat signs and endings are dropped from the result.
+pub fn unroll_repeats(block: &Block) -> Block {
+    play_repeats(block, false)
+}
+
+/// Writes out a section as [`unroll_repeats`] does, or with `once`, playing
+/// each repeat a single time with its last ending, as after a D.S. or D.C.
+fn play_repeats(block: &Block, once: bool) -> Block {
+    let segments = block_segments(block);
+
+    let mut lines: Vec<LyricLine> = Vec::new();
+    // The source line each written-out line comes from.
+    let mut sources: Vec<usize> = Vec::new();
+    let mut previous: Option<usize> = None;
+    for index in play_order(&segments, once) {
+        let (line, segment) = segments[index];
+        let same_line = previous.is_some_and(|p| p + 1 == index && segments[p].0 == line);
+        if !same_line || lines.is_empty() {
+            lines.push(LyricLine { segments: Vec::new(), pickup: block.lines[line].pickup, span: block.lines[line].span });
+            sources.push(line);
+        }
+        if let Some(current) = lines.last_mut() {
+            current.segments.push(without_repeats(segment));
+        }
+        previous = Some(index);
+    }
+
+    // A directive moves to the first time its line, or a later one, is played.
+    let directives = block
+        .directives
+        .iter()
+        .map(|d| InlineDirective {
+            line: sources.iter().position(|source| *source >= d.line).unwrap_or(lines.len()),
+            ..d.clone()
+        })
+        .collect();
+
+    Block {
+        section_name: block.section_name.clone(),
+        section: block.section.clone(),
+        lines,
+        directives,
+        is_reference: block.is_reference,
+        span: block.span,
+    }
+}
+
+/// Every segment of a block with the line it is on.
+fn block_segments(block: &Block) -> Vec<(usize, &Segment)> {
+    block
+        .lines
+        .iter()
+        .enumerate()
+        .flat_map(|(line, l)| l.segments.iter().map(move |s| (line, s)))
+        .collect()
+}
+
+fn markers(block: &Block) -> impl Iterator<Item = NavigationMarker> + '_ {
+    block.lines.iter().flat_map(|line| &line.segments).flat_map(|segment| segment.items()).filter_map(|item| match item {
+        ChordOrText::Marker(marker, _) => Some(*marker),
+        _ => None,
+    })
+}
+
+/// The first segment at or after `from` (a section and segment index) that
+/// holds `wanted`.
+fn find_marker(blocks: &[Block], wanted: NavigationMarker, from: (usize, usize)) -> Option<(usize, usize)> {
+    blocks.iter().enumerate().skip(from.0).find_map(|(section, block)| {
+        let skip = if section == from.0 { from.1 } else { 0 };
+        block_segments(block)
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .find(|(_, (_, segment))| has_marker(segment, wanted))
+            .map(|(index, _)| (section, index))
+    })
+}
+
+fn has_marker(segment: &Segment, wanted: NavigationMarker) -> bool {
+    segment.items().iter().any(|item| matches!(item, ChordOrText::Marker(marker, _) if *marker == wanted))
+}
+
+/// The part of a block from segment `start` up to, but not including, `end`,
+/// with the directives written among those segments.
+fn excerpt(block: &Block, start: usize, end: usize) -> Block {
+    let segments = block_segments(block);
+    if start == 0 && end == segments.len() {
+        return block.clone();
+    }
+    let mut lines: Vec<LyricLine> = Vec::new();
+    let mut previous: Option<usize> = None;
+    for (line, segment) in &segments[start..end] {
+        if previous != Some(*line) {
+            lines.push(LyricLine { segments: Vec::new(), ..block.lines[*line].clone() });
+        }
+        if let Some(current) = lines.last_mut() {
+            current.segments.push((*segment).clone());
+        }
+        previous = Some(*line);
+    }
+
+    // A directive before the first line only belongs if that line is whole.
+    let first = segments.get(start).map_or(block.lines.len(), |(line, _)| *line);
+    let starts_line = start == 0 || segments[start - 1].0 != first;
+    let last = previous.unwrap_or(first);
+    let directives = block
+        .directives
+        .iter()
+        .filter(|d| (d.line > first || (d.line == first && starts_line)) && (d.line <= last || end == segments.len()))
+        .map(|d| InlineDirective { line: d.line - first, ..d.clone() })
+        .collect();
+    Block { lines, directives, ..block.clone() }
+}
+
+/// Splits the lyric lines of a block so that every road-map marker falls at
+/// the edge of a segment: `{Segno}` and `{Coda}` start one, the other markers
+/// end one. Measures are left whole, as a marker in a bar applies to all of it.
+fn split_at_markers(block: &Block) -> Block {
+    let mut block = block.clone();
+    for line in &mut block.lines {
+        line.segments = std::mem::take(&mut line.segments).into_iter().flat_map(split_segment).collect();
+    }
+    block
+}
+
+fn split_segment(segment: Segment) -> Vec<Segment> {
+    let Segment::Inline(items, span) = segment else {
+        return vec![segment];
+    };
+    let mut parts: Vec<Vec<ChordOrText>> = vec![Vec::new()];
+    for item in items {
+        let (starts, ends) = match item {
+            ChordOrText::Marker(NavigationMarker::Segno | NavigationMarker::Coda, _) => (true, false),
+            ChordOrText::Marker(..) => (false, true),
+            _ => (false, false),
+        };
+        if starts && parts.last().is_some_and(|part| !part.is_empty()) {
+            parts.push(Vec::new());
+        }
+        if let Some(part) = parts.last_mut() {
+            part.push(item);
+        }
+        if ends {
+            parts.push(Vec::new());
+        }
+    }
+    parts.into_iter().filter(|part| !part.is_empty()).map(|items| Segment::Inline(items, span)).collect()
+}
+
+/// A block without its road-map markers, once they have been followed.
+fn without_markers(block: &Block) -> Block {
+    let mut block = block.clone();
+    for segment in block.lines.iter_mut().flat_map(|line| &mut line.segments) {
+        let items = match segment {
+            Segment::Measure(measure) => &mut measure.items,
+            Segment::Inline(items, _) => items,
+        };
+        items.retain(|item| !matches!(item, ChordOrText::Marker(..)));
+    }
+    block
+}
+
+fn measure<'a>(segments: &[(usize, &'a Segment)], index: usize) -> Option<&'a Measure> {
+    match segments.get(index) {
+        Some((_, Segment::Measure(measure))) => Some(measure),
+        _ => None,
+    }
+}
+
+/// The indexes of `segments` in the order they are played, or with `once`,
+/// the order of the last pass through each repeat.
+fn play_order(segments: &[(usize, &Segment)], once: bool) -> Vec<usize> {
+    let first_pass = |start: usize| if once { last_pass(segments, start) } else { 1 };
+    let mut order = Vec::new();
+    let mut repeat_start = 0;
+    // The `:|` we last jumped back from, while its repeat is still going.
+    let mut repeat_end: Option<usize> = None;
+    let mut pass = first_pass(0);
+    let mut index = 0;
+
+    while index < segments.len() {
+        if let Some(current) = measure(segments, index) {
+            if current.start.starts_repeat() && index != repeat_start {
+                repeat_start = index;
+                pass = first_pass(index);
+            }
+            match &current.volta {
+                Some(volta) if !volta.endings.contains(&pass) => {
+                    index = ending_end(segments, index);
+                    continue;
+                }
+                None if repeat_end.is_some_and(|end| index > end) => {
+                    repeat_start = index;
+                    repeat_end = None;
+                    pass = 1;
+                }
+                _ => {}
+            }
+        }
+
+        order.push(index);
+
+        if let Some(current) = measure(segments, index)
+            && current.end.is_some_and(Barline::ends_repeat) {
+            let times = current.repeat_count.unwrap_or_else(|| passes(segments, repeat_start));
+            if pass < times {
+                pass += 1;
+                repeat_end = Some(index);
+                index = repeat_start;
+                continue;
+            }
+            repeat_start = index + 1;
+            repeat_end = None;
+            pass = first_pass(index + 1);
+        }
+        index += 1;
+    }
+    order
+}
+
+/// The number of the last pass through the repeat starting at `start`.
+fn last_pass(segments: &[(usize, &Segment)], start: usize) -> u32 {
+    (start..segments.len())
+        .filter_map(|index| measure(segments, index))
+        .find(|m| m.end.is_some_and(Barline::ends_repeat))
+        .and_then(|m| m.repeat_count)
+        .unwrap_or_else(|| passes(segments, start))
+}
+
+/// How many times the repeat starting at `start` is played without an `x`
+/// count: twice, or once per ending if it has more than two.
+fn passes(segments: &[(usize, &Segment)], start: usize) -> u32 {
+    (start..segments.len())
+        .map_while(|index| match measure(segments, index) {
+            Some(m) if index > start && m.start.starts_repeat() => None,
+            m => Some(m),
+        })
+        .flatten()
+        .filter_map(|m| m.volta.as_ref())
+        .flat_map(|volta| volta.endings.iter().copied())
+        .fold(2, u32::max)
+}
+
+/// Where the ending starting at `start` stops (exclusive): at the next ending,
+/// or after the first measure closed by anything but a single barline.
+fn ending_end(segments: &[(usize, &Segment)], start: usize) -> usize {
+    for index in start..segments.len() {
+        let Some(current) = measure(segments, index) else {
+            continue;
+        };
+        if index > start && current.volta.is_some() {
+            return index;
+        }
+        if current.end.is_some_and(|end| end != Barline::Single) {
+            return index + 1;
+        }
+    }
+    segments.len()
+}
+
+fn without_repeats(segment: &Segment) -> Segment {
+    let plain = |bar: Barline| match bar {
+        Barline::StartRepeat | Barline::EndRepeat | Barline::EndStartRepeat => Barline::Single,
+        other => other,
+    };
+    match segment {
+        Segment::Measure(measure) => Segment::Measure(Measure {
+            start: plain(measure.start),
+            volta: None,
+            end: measure.end.map(plain),
+            repeat_count: None,
+            ..measure.clone()
+        }),
+        Segment::Inline(..) => segment.clone(),
+    }
+}
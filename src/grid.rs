@@ -0,0 +1,306 @@
+use crate::ast::*;
+use crate::render::{self, LeadSheetRenderer, RenderOptions, Section};
+use crate::transpose;
+use markup_engine::engine::MarkupEngine;
+use std::fmt::Write;
+use unicode_width::UnicodeWidthStr;
+
+/// The markup a grid is written in. Markup engines have no tables of their
+/// own, so the grid renderer writes them itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TableFormat {
+    /// A pipe table, as on GitHub.
+    #[default]
+    Markdown,
+    Html,
+}
+
+impl TableFormat {
+    /// A guess at the tables that go with `engine`'s markup: an HTML table for
+    /// an engine that writes tags, and a pipe table for anything else.
+    pub fn of(engine: &dyn MarkupEngine) -> Self {
+        if engine.bold("").starts_with('<') { TableFormat::Html } else { TableFormat::Markdown }
+    }
+
+    /// How wide a cell is where widths are measured: in the Markdown
+    /// source, where the markup takes up room, or on the rendered page.
+    fn cell_width(self, cell: &Cell) -> usize {
+        match self {
+            TableFormat::Markdown => markdown_cell(cell).width(),
+            TableFormat::Html => cell.width,
+        }
+    }
+}
+
+/// Lays chord-only sections out as a grid of bars, as in a Real Book: the
+/// same number of equally wide bars in every row, with the section label in
+/// the margin and a pickup just before the first barline. Sections with
+/// lyrics are drawn chords over lyrics, as by
+/// [`DefaultLeadSheetRenderer`](crate::render::DefaultLeadSheetRenderer).
+#[derive(Debug, Clone)]
+pub struct GridLeadSheetRenderer {
+    pub bars_per_row: usize,
+    /// The table markup to write, or `None` to go by the engine, see
+    /// [`TableFormat::of`].
+    pub format: Option<TableFormat>,
+}
+
+impl Default for GridLeadSheetRenderer {
+    fn default() -> Self {
+        GridLeadSheetRenderer { bars_per_row: 4, format: None }
+    }
+}
+
+/// A table cell: its markup and how many columns its text takes up.
+#[derive(Debug, Clone, Default)]
+struct Cell {
+    text: String,
+    width: usize,
+}
+
+impl Cell {
+    fn new(engine: &dyn MarkupEngine, text: &str) -> Self {
+        Cell { text: engine.line_segment(text), width: text.width() }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Row {
+    margin: Cell,
+    bars: Vec<Cell>,
+}
+
+/// The chart in drawing order: grids, and everything drawn between them.
+enum Piece<'a> {
+    Grid(Vec<Row>),
+    Changes(Vec<String>),
+    Section(&'a Section<'a>),
+}
+
+impl LeadSheetRenderer for GridLeadSheetRenderer {
+    fn render_song_with_options(&self, engine: &dyn MarkupEngine, song: &Song, options: &RenderOptions) -> String {
+        let capo = render::capo_fret(song, options);
+        let shapes = (capo > 0).then(|| transpose::transpose_song(song.clone(), -(capo as isize)).0);
+        let chart = shapes.as_ref().unwrap_or(song);
+
+        let mut output = render::render_song_header(engine, song, chart, capo);
+        let marks = chart.rehearsal_marks();
+        let numbers = if options.measure_numbers { chart.numbered_measures() } else { Vec::new() };
+        let sections = render::sections(song, chart, &marks, &numbers, capo, options);
+
+        let mut pieces: Vec<Piece> = Vec::new();
+        for section in &sections {
+            if section.is_collapsed(options) || !is_chord_only(section.block) {
+                pieces.push(Piece::Section(section));
+            } else {
+                self.lay_out(engine, section, options, &mut pieces);
+            }
+        }
+
+        // Every grid shares one bar width, so rows line up down the page.
+        let rows = pieces.iter().flat_map(|piece| match piece {
+            Piece::Grid(rows) => rows.as_slice(),
+            _ => &[],
+        });
+        let format = self.format.unwrap_or_else(|| TableFormat::of(engine));
+        let (margin_width, bar_width) = rows.fold((0, 4), |(margin, bar), row| {
+            let widest = row.bars.iter().map(|cell| format.cell_width(cell)).max().unwrap_or(0);
+            (margin.max(format.cell_width(&row.margin)), bar.max(widest))
+        });
+
+        for piece in &pieces {
+            match piece {
+                Piece::Grid(rows) => {
+                    // A Markdown table has to start a new paragraph.
+                    while format == TableFormat::Markdown && !output.is_empty() && !output.ends_with("\n\n") {
+                        output.push('\n');
+                    }
+                    output.push_str(&self.render_grid(engine, format, rows, margin_width, bar_width));
+                }
+                Piece::Changes(changes) => render::push_changes(engine, &mut output, changes),
+                Piece::Section(section) => render::render_section(engine, &mut output, section, options),
+            }
+        }
+        output
+    }
+}
+
+impl GridLeadSheetRenderer {
+    /// Flows the bars of a section into rows of `bars_per_row`. A change of
+    /// key or time ends the grid so it can be printed before the next bar, and
+    /// a pickup starts a new row.
+    fn lay_out<'a>(&self, engine: &dyn MarkupEngine, section: &Section, options: &RenderOptions, pieces: &mut Vec<Piece<'a>>) {
+        let mut rows: Vec<Row> = Vec::new();
+        let mut row = Row { margin: label(engine, &section.title()), bars: Vec::new() };
+        let mut pickup: Option<String> = None;
+
+        for line_index in 0..=section.block.lines.len() {
+            let changes = render::render_changes(section, line_index);
+            if !changes.is_empty() {
+                if !row.bars.is_empty() {
+                    rows.push(std::mem::take(&mut row));
+                }
+                push_rows(pieces, std::mem::take(&mut rows));
+                pieces.push(Piece::Changes(changes));
+            }
+            let Some(line) = section.block.lines.get(line_index) else {
+                continue;
+            };
+            for (segment_index, segment) in line.segments.iter().enumerate() {
+                let Segment::Measure(measure) = segment else {
+                    continue;
+                };
+                let concert = section
+                    .concert_block
+                    .and_then(|b| b.lines.get(line_index))
+                    .and_then(|l| l.segments.get(segment_index));
+                let mut text = bar_text(measure, concert, options);
+                if measure.pickup {
+                    if !row.bars.is_empty() {
+                        rows.push(std::mem::take(&mut row));
+                    }
+                    pickup = Some(text);
+                    continue;
+                }
+                if let Some(pickup) = pickup.take() {
+                    let margin = match row.margin.width {
+                        0 => Cell::new(engine, &pickup),
+                        _ => Cell { text: format!("{} {}", row.margin.text, engine.line_segment(&pickup)), width: row.margin.width + 1 + pickup.width() },
+                    };
+                    row.margin = margin;
+                }
+                if row.bars.is_empty()
+                    && let Some(number) = section.number(line_index, segment_index) {
+                    text.insert_str(0, &format!("{} ", number));
+                }
+                row.bars.push(Cell::new(engine, &text));
+                if row.bars.len() >= self.bars_per_row.max(1) {
+                    rows.push(std::mem::take(&mut row));
+                }
+            }
+        }
+        if !row.bars.is_empty() {
+            rows.push(row);
+        }
+        push_rows(pieces, rows);
+    }
+
+    fn render_grid(&self, engine: &dyn MarkupEngine, format: TableFormat, rows: &[Row], margin_width: usize, bar_width: usize) -> String {
+        let columns = self.bars_per_row.max(1);
+        let mut output = String::new();
+        match format {
+            TableFormat::Markdown => {
+                let margin_width = margin_width.max(3);
+                let pad = |cell: &Cell, width: usize| {
+                    let text = markdown_cell(cell);
+                    let padding = " ".repeat(width.saturating_sub(text.width()));
+                    (text, padding)
+                };
+                write!(&mut output, "| {} |", " ".repeat(margin_width)).unwrap();
+                for _ in 0..columns {
+                    write!(&mut output, " {} |", " ".repeat(bar_width)).unwrap();
+                }
+                write!(&mut output, "\n| {}: |", "-".repeat(margin_width - 1)).unwrap();
+                for _ in 0..columns {
+                    write!(&mut output, " {} |", "-".repeat(bar_width)).unwrap();
+                }
+                output.push('\n');
+                for row in rows {
+                    let (margin, padding) = pad(&row.margin, margin_width);
+                    write!(&mut output, "| {}{} |", padding, margin).unwrap();
+                    for index in 0..columns {
+                        let (bar, padding) = row.bars.get(index).map_or((String::new(), " ".repeat(bar_width)), |cell| pad(cell, bar_width));
+                        write!(&mut output, " {}{} |", bar, padding).unwrap();
+                    }
+                    output.push('\n');
+                }
+                output.push_str(&engine.linebreak());
+            }
+            TableFormat::Html => {
+                output.push_str("<table style=\"table-layout: fixed\">\n<colgroup>");
+                write!(&mut output, "<col style=\"width: {}ch\">", margin_width + 2).unwrap();
+                for _ in 0..columns {
+                    write!(&mut output, "<col style=\"width: {}ch\">", bar_width + 2).unwrap();
+                }
+                output.push_str("</colgroup>\n");
+                for row in rows {
+                    write!(&mut output, "<tr><th scope=\"row\" style=\"text-align: right\">{}</th>", row.margin.text).unwrap();
+                    for index in 0..columns {
+                        let bar = row.bars.get(index).map_or("", |cell| cell.text.as_str());
+                        write!(&mut output, "<td>{}</td>", bar).unwrap();
+                    }
+                    output.push_str("</tr>\n");
+                }
+                output.push_str("</table>\n");
+                output.push_str(&engine.linebreak());
+            }
+        }
+        output
+    }
+}
+
+/// Adds rows to the grid being drawn, so back-to-back sections share a table.
+fn push_rows(pieces: &mut Vec<Piece>, mut rows: Vec<Row>) {
+    match pieces.last_mut() {
+        _ if rows.is_empty() => {}
+        Some(Piece::Grid(grid)) => grid.append(&mut rows),
+        _ => pieces.push(Piece::Grid(rows)),
+    }
+}
+
+/// A cell's markup with its pipes escaped, so they don't split the cell.
+fn markdown_cell(cell: &Cell) -> String {
+    cell.text.replace('|', "\\|")
+}
+
+fn label(engine: &dyn MarkupEngine, title: &str) -> Cell {
+    Cell { text: engine.bold(title), width: title.width() }
+}
+
+/// Sections made only of measures, with no lyrics in them.
+fn is_chord_only(block: &Block) -> bool {
+    let mut segments = block.lines.iter().flat_map(|line| &line.segments).peekable();
+    segments.peek().is_some()
+        && segments.all(|segment| match segment {
+            Segment::Measure(measure) => !measure.items.iter().any(|item| matches!(item, ChordOrText::Text(..))),
+            Segment::Inline(..) => false,
+        })
+}
+
+/// A bar as one cell: its chords and symbols, with any repeat sign, ending or
+/// special barline written inside it. Single barlines are the cell borders.
+fn bar_text(measure: &Measure, concert: Option<&Segment>, options: &RenderOptions) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    if measure.start.starts_repeat() {
+        parts.push("|:".to_string());
+    }
+    if let Some(volta) = &measure.volta {
+        parts.push(volta.label());
+    }
+    let mut concert_chords = concert.into_iter().flat_map(|s| s.items()).filter_map(|item| match item {
+        ChordOrText::Chord(chord) => Some(chord),
+        _ => None,
+    });
+    for item in &measure.items {
+        match item {
+            ChordOrText::Chord(chord) => {
+                let mut chord = chord.to_string();
+                if let Some(concert_chord) = concert_chords.next() {
+                    write!(&mut chord, " ({})", concert_chord).unwrap();
+                }
+                parts.push(chord);
+            }
+            ChordOrText::Text(..) => {}
+            _ => parts.push(render::symbol_text(item, options).to_string()),
+        }
+    }
+    match measure.end {
+        Some(end) if end.ends_repeat() => match measure.repeat_count {
+            Some(count) => parts.push(format!(":| x{}", count)),
+            None => parts.push(":|".to_string()),
+        },
+        Some(end @ (Barline::Double | Barline::Final)) => parts.push(end.symbol().to_string()),
+        _ => {}
+    }
+    parts.join(" ")
+}
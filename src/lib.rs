@@ -1,5 +1,9 @@
 pub mod parser;
 pub mod ast;
+pub mod metadata;
 pub mod render;
+pub mod form;
+pub mod grid;
 pub mod transpose;
-pub mod diagnostics;
\ No newline at end of file
+pub mod diagnostics;
+pub mod report;
\ No newline at end of file
@@ -2,18 +2,14 @@ use clap::Parser;
 use std::{fs};
 
 use markup_engine::{HtmlEngine, MarkdownEngine};
-use render::DefaultLeadSheetRenderer;
-use crate::render::LeadSheetRenderer;
-mod transpose;
-
-mod parser;
-mod ast;
-mod render;
-mod diagnostics;
+use leadsheetml::{form, parser, report, transpose};
+use leadsheetml::diagnostics::{self, DiagnosticSeverity};
+use leadsheetml::grid::{GridLeadSheetRenderer, TableFormat};
+use leadsheetml::render::{DefaultLeadSheetRenderer, LeadSheetRenderer, RenderOptions};
+use leadsheetml::transpose::Spelling;
 
 #[derive(clap::Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-
 struct Args {
     /// Path to the .lmpl file
     #[arg()]
@@ -23,43 +19,149 @@ struct Args {
     #[arg(long, value_parser = ["markdown", "html"], default_value = "markdown")]
     format: String,
 
+    /// Page layout: chords over lyrics, or chord-only sections as a grid of bars
+    #[arg(long, value_parser = ["lyrics", "grid"], default_value = "lyrics")]
+    layout: String,
+
+    /// Bars per row in the grid layout
+    #[arg(long = "bars-per-row", default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    bars_per_row: u16,
+
     #[arg(long, default_value_t = 0)]
     transpose: isize,
 
     #[arg(long, default_value_t = 0)]
-    transpose_down: isize
+    transpose_down: isize,
+
+    /// Transpose into a named key, e.g. "Eb Major" or just "Bb"
+    #[arg(long = "to-key", conflicts_with_all = ["transpose", "transpose_down"])]
+    to_key: Option<String>,
+
+    /// Accidentals for transposed chords (default follows the destination key)
+    #[arg(long, value_parser = ["auto", "sharps", "flats"], default_value = "auto")]
+    spelling: String,
+
+    /// Render the chord shapes for a capo on this fret (overrides @capo)
+    #[arg(long)]
+    capo: Option<usize>,
+
+    /// With a capo, also show the concert chord next to each shape
+    #[arg(long = "concert-chords")]
+    concert_chords: bool,
+
+    /// Treat warnings as errors
+    #[arg(long = "deny-warnings")]
+    deny_warnings: bool,
+
+    /// Skip lines that fail to parse and render the rest of the song
+    #[arg(long)]
+    recover: bool,
+
+    /// Print every section in performance order, following @form and writing out repeats
+    #[arg(long = "expand-form")]
+    expand_form: bool,
+
+    /// Print reused sections like #Chorus* in full instead of as a reference line
+    #[arg(long = "full-references")]
+    full_references: bool,
+
+    /// Number the bars; with --expand-form, repeats are counted as played
+    #[arg(long = "measure-numbers")]
+    measure_numbers: bool,
+
+    /// Letter the sections A, B, C... (an @mark in a section overrides its letter)
+    #[arg(long = "rehearsal-marks")]
+    rehearsal_marks: bool
 }
 
 
+/// Exit codes returned by the CLI; clap itself exits with 2 on bad arguments.
+const EXIT_INVALID_FILE: i32 = 1;
+const EXIT_READ_FAILED: i32 = 3;
+const EXIT_PARSE_FAILED: i32 = 4;
+const EXIT_TRANSPOSE_FAILED: i32 = 5;
+const EXIT_WARNINGS_DENIED: i32 = 6;
+
+fn fail(message: impl std::fmt::Display, code: i32) -> ! {
+    eprintln!("{}", message.to_string().trim_end());
+    std::process::exit(code);
+}
+
 fn main() {
     let args = Args::parse();
 
     if !args.filename.ends_with(".lsml") {
-        eprintln!("Invalid file extension: {}", args.filename);
-        std::process::exit(1);
+        fail(format!("Invalid file extension: {}", args.filename), EXIT_INVALID_FILE);
     }
 
-    let input = fs::read_to_string(&args.filename).expect("Failed to read input file");
-    let mut ast = parser::parse_song_from_str(&input).expect("AST should be correct or fail");
-    if args.transpose != 0 {
-        ast = transpose::transpose_song(
+    let spelling = match args.spelling.as_str() {
+        "sharps" => Spelling::Sharps,
+        "flats" => Spelling::Flats,
+        _ => Spelling::Auto,
+    };
+
+    let input = fs::read_to_string(&args.filename)
+        .unwrap_or_else(|e| fail(format!("Failed to read {}: {}", args.filename, e), EXIT_READ_FAILED));
+    let report = |error| {
+        let diagnostic = diagnostics::error_to_diagnostic(&error, &input);
+        report::render_report(&input, &args.filename, &diagnostic)
+    };
+    let (mut ast, mut warnings) = if args.recover {
+        parser::parse_song_recovering(&input)
+    } else {
+        parser::parse_song_with_diagnostics(&input)
+            .unwrap_or_else(|e| fail(report(e), EXIT_PARSE_FAILED))
+    };
+    let semitones = args.transpose - args.transpose_down;
+    if semitones != 0 || (spelling != Spelling::Auto && args.to_key.is_none()) {
+        let (song, transpose_warnings) = transpose::transpose_song_with_spelling(
             ast,
-            args.transpose)
+            semitones,
+            spelling);
+        warnings.extend(transpose_warnings);
+        ast = song;
     }
-    if args.transpose_down != 0 {
-        ast = transpose::transpose_song(
-            ast,
-            -args.transpose_down)
+    if let Some(target) = &args.to_key {
+        ast = transpose::transpose_to_key_with_spelling(ast, target, spelling)
+            .unwrap_or_else(|e| fail(report(e), EXIT_TRANSPOSE_FAILED));
+    }
+    if args.expand_form {
+        ast = form::expand_form(&ast);
+    }
+    for warning in &warnings {
+        eprint!("{}", report::render_report(&input, &args.filename, warning));
     }
+    let error_count = warnings.iter().filter(|d| d.severity == DiagnosticSeverity::Error).count();
+    let warning_count = warnings.len() - error_count;
+    if args.deny_warnings && warning_count > 0 {
+        fail(format!("{} warning(s) denied by --deny-warnings", warning_count), EXIT_WARNINGS_DENIED);
+    }
+    let options = RenderOptions {
+        capo: args.capo,
+        show_concert_chords: args.concert_chords,
+        music_symbols: args.format == "html",
+        full_references: args.full_references || args.expand_form,
+        measure_numbers: args.measure_numbers,
+        rehearsal_marks: args.rehearsal_marks,
+    };
+    let renderer: Box<dyn LeadSheetRenderer> = match args.layout.as_str() {
+        "grid" => Box::new(GridLeadSheetRenderer {
+            bars_per_row: args.bars_per_row as usize,
+            format: Some(if args.format == "html" { TableFormat::Html } else { TableFormat::Markdown }),
+        }),
+        _ => Box::new(DefaultLeadSheetRenderer),
+    };
     match args.format.as_str() {
         "html" => {
-            let html = DefaultLeadSheetRenderer.render_song(&HtmlEngine, &ast);
+            let html = renderer.render_song_with_options(&HtmlEngine, &ast, &options);
             println!("{}", html);
         }
         _ => {
-            let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &ast);
+            let md = renderer.render_song_with_options(&MarkdownEngine, &ast, &options);
             println!("{}", md);
         }
     }
+    if error_count > 0 {
+        fail(format!("{} error(s); the input at fault was skipped", error_count), EXIT_PARSE_FAILED);
+    }
 }
-
@@ -0,0 +1,320 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ast::*;
+
+/// Why a directive value could not be read as its typed form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataError {
+    pub message: String,
+}
+
+impl MetadataError {
+    fn new(message: impl Into<String>) -> Self {
+        MetadataError { message: message.into() }
+    }
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl FromStr for Note {
+    type Err = MetadataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || MetadataError::new(format!("'{}' is not a note name like C, F# or Bb", s));
+        let mut chars = s.chars();
+        let letter = match chars.next().ok_or_else(invalid)?.to_ascii_uppercase() {
+            'A' => NoteLetter::A,
+            'B' => NoteLetter::B,
+            'C' => NoteLetter::C,
+            'D' => NoteLetter::D,
+            'E' => NoteLetter::E,
+            'F' => NoteLetter::F,
+            'G' => NoteLetter::G,
+            _ => return Err(invalid()),
+        };
+        let accidental = match chars.next() {
+            Some('#') => Accidental::Sharp,
+            Some('b') => Accidental::Flat,
+            None => Accidental::None,
+            Some(_) => return Err(invalid()),
+        };
+        if chars.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Note { letter, accidental })
+    }
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.letter)?;
+        match self.accidental {
+            Accidental::Sharp => write!(f, "#"),
+            Accidental::Flat => write!(f, "b"),
+            Accidental::None => Ok(()),
+        }
+    }
+}
+
+/// The mode of a key. `Major`/`Ionian` and `Minor`/`Aeolian` are kept apart
+/// so a key is written back the way the author spelled it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Major,
+    Minor,
+    Ionian,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Aeolian,
+    Locrian,
+}
+
+impl Mode {
+    /// Scale degree of the relative major this mode starts on (0 = Ionian).
+    pub fn degree(self) -> usize {
+        match self {
+            Mode::Major | Mode::Ionian => 0,
+            Mode::Dorian => 1,
+            Mode::Phrygian => 2,
+            Mode::Lydian => 3,
+            Mode::Mixolydian => 4,
+            Mode::Minor | Mode::Aeolian => 5,
+            Mode::Locrian => 6,
+        }
+    }
+
+    /// Whether two modes share a scale, e.g. `Minor` and `Aeolian`.
+    pub fn is_equivalent(self, other: Mode) -> bool {
+        self.degree() == other.degree()
+    }
+}
+
+impl FromStr for Mode {
+    type Err = MetadataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "major" | "maj" => Ok(Mode::Major),
+            "minor" | "min" => Ok(Mode::Minor),
+            "ionian" => Ok(Mode::Ionian),
+            "dorian" => Ok(Mode::Dorian),
+            "phrygian" => Ok(Mode::Phrygian),
+            "lydian" => Ok(Mode::Lydian),
+            "mixolydian" => Ok(Mode::Mixolydian),
+            "aeolian" => Ok(Mode::Aeolian),
+            "locrian" => Ok(Mode::Locrian),
+            _ => Err(MetadataError::new(format!("'{}' is not a mode like Major, Minor or Dorian", s))),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// A `@key` directive such as `Eb Major`, `C# Minor` or `D Dorian`.
+/// A bare tonic (`G`) is major and a trailing `m` (`Em`) is minor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Key {
+    pub tonic: Note,
+    pub mode: Mode,
+}
+
+impl FromStr for Key {
+    type Err = MetadataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            [tonic, mode] => Ok(Key { tonic: tonic.parse()?, mode: mode.parse()? }),
+            [tonic] => match tonic.strip_suffix('m') {
+                Some(minor) if minor.parse::<Note>().is_ok() => Ok(Key { tonic: minor.parse()?, mode: Mode::Minor }),
+                _ => Ok(Key { tonic: tonic.parse()?, mode: Mode::Major }),
+            },
+            _ => Err(MetadataError::new(format!("'{}' is not a key like C Major or F# Minor", s))),
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.tonic, self.mode)
+    }
+}
+
+/// A `@time` directive such as `4/4` or `6/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub beats: u32,
+    pub unit: u32,
+}
+
+impl TimeSignature {
+    /// The most beats a bar can have, and so the longest a chord can last.
+    pub const MAX_BEATS: u32 = 64;
+}
+
+impl FromStr for TimeSignature {
+    type Err = MetadataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || MetadataError::new(format!("'{}' is not a time signature like 4/4 or 6/8", s));
+        let (beats, unit) = s.trim().split_once('/').ok_or_else(invalid)?;
+        let beats: u32 = beats.trim().parse().map_err(|_| invalid())?;
+        let unit: u32 = unit.trim().parse().map_err(|_| invalid())?;
+        if beats == 0 {
+            return Err(MetadataError::new(format!("time signature '{}' has no beats", s)));
+        }
+        if beats > TimeSignature::MAX_BEATS {
+            return Err(MetadataError::new(format!("time signature '{}' has more than {} beats", s, TimeSignature::MAX_BEATS)));
+        }
+        if !unit.is_power_of_two() || unit > 64 {
+            return Err(MetadataError::new(format!("time signature '{}' has a beat unit that is not a note value", s)));
+        }
+        Ok(TimeSignature { beats, unit })
+    }
+}
+
+impl fmt::Display for TimeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.beats, self.unit)
+    }
+}
+
+/// A `@tempo` directive: a metronome mark, a description, or both, e.g.
+/// `120`, `Med Swing` or `Ballad 72 BPM`. It is written back in one form,
+/// the description first and the mark with `BPM`: `Med Swing 160` and
+/// `160 bpm Med Swing` both become `Med Swing 160 BPM`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tempo {
+    pub bpm: Option<u32>,
+    pub text: Option<String>,
+}
+
+impl FromStr for Tempo {
+    type Err = MetadataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens: Vec<&str> = s.split_whitespace().collect();
+        let is_unit = |index: usize| tokens.get(index).is_some_and(|t| t.eq_ignore_ascii_case("bpm"));
+        let numbers: Vec<usize> = (0..tokens.len()).filter(|&i| tokens[i].parse::<u32>().is_ok()).collect();
+        // The number marked `BPM` is the metronome mark; without one, the first
+        // number is, so a description may still hold numbers of its own.
+        let mark = numbers.iter().copied().find(|&i| is_unit(i + 1)).or(numbers.first().copied());
+        let mut bpm = None;
+        let mut words = Vec::new();
+        for (index, token) in tokens.iter().enumerate() {
+            if Some(index) == mark {
+                let value: u32 = token.parse().unwrap_or(0);
+                if value == 0 {
+                    return Err(MetadataError::new(format!("tempo '{}' must be above 0 BPM", s)));
+                }
+                bpm = Some(value);
+            } else if !(mark.is_some_and(|m| m + 1 == index) && is_unit(index)) {
+                words.push(*token);
+            }
+        }
+        if bpm.is_none() && words.is_empty() {
+            return Err(MetadataError::new("tempo is empty"));
+        }
+        let text = (!words.is_empty()).then(|| words.join(" "));
+        Ok(Tempo { bpm, text })
+    }
+}
+
+impl fmt::Display for Tempo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.text, self.bpm) {
+            (Some(text), Some(bpm)) => write!(f, "{} {} BPM", text, bpm),
+            (Some(text), None) => write!(f, "{}", text),
+            (None, Some(bpm)) => write!(f, "{} BPM", bpm),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// A `@form` directive: the order sections are played in, e.g.
+/// `Intro V1 C V2 C x2 Outro`. Names with spaces are separated by commas
+/// instead: `Intro, Verse 1, Chorus x2`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Form {
+    pub entries: Vec<FormEntry>,
+}
+
+/// One step of a [`Form`]: a section name and how many times in a row it is
+/// played.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormEntry {
+    pub section: String,
+    pub times: u32,
+}
+
+impl FromStr for Form {
+    type Err = MetadataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pieces: Vec<&str> = if s.contains(',') {
+            s.split(',').map(str::trim).collect()
+        } else {
+            s.split_whitespace().collect()
+        };
+        let mut entries: Vec<FormEntry> = Vec::new();
+        for piece in pieces {
+            if piece.is_empty() {
+                return Err(MetadataError::new(format!("form '{}' has an empty section name", s)));
+            }
+            // A count on its own, like the `x2` in `C x2`, applies to the section before it.
+            let (section, times) = match (repeat_count(piece), piece.rsplit_once(char::is_whitespace)) {
+                (Some(times), _) => ("", times),
+                (None, Some((section, count))) if repeat_count(count).is_some() => (section.trim_end(), repeat_count(count).unwrap_or(1)),
+                (None, _) => (piece, 1),
+            };
+            if times == 0 {
+                return Err(MetadataError::new(format!("'{}' plays a section zero times", piece)));
+            }
+            if !section.is_empty() {
+                entries.push(FormEntry { section: section.to_string(), times });
+                continue;
+            }
+            match entries.last_mut() {
+                Some(previous) => previous.times = times,
+                None => return Err(MetadataError::new(format!("'{}' has no section to repeat", piece))),
+            }
+        }
+        if entries.is_empty() {
+            return Err(MetadataError::new("form is empty"));
+        }
+        Ok(Form { entries })
+    }
+}
+
+/// Reads a repeat count like `x2` or `×2`.
+pub(crate) fn repeat_count(text: &str) -> Option<u32> {
+    text.strip_prefix(['x', 'X', '×'])?.parse().ok()
+}
+
+impl fmt::Display for FormEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.section)?;
+        if self.times > 1 {
+            write!(f, " x{}", self.times)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Form {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if self.entries.iter().any(|e| e.section.contains(char::is_whitespace)) { ", " } else { " " };
+        let entries: Vec<String> = self.entries.iter().map(FormEntry::to_string).collect();
+        write!(f, "{}", entries.join(separator))
+    }
+}
@@ -1,35 +1,409 @@
-use std::collections::HashMap;
+use std::ops::Range;
 use pest::iterators::Pair;
 use pest::Parser;
 use pest_derive::Parser;
 
 use crate::ast::*;
 use crate::diagnostics::*;
+use crate::metadata::*;
 
 #[derive(Parser)]
 #[grammar = "leadsheetml.pest"]
 pub struct LeadSheetMLParser;
 
 pub fn parse_song_from_str(input: &str) -> ParseResult<Song, Rule> {
+    parse_song_with_options(input, &ParseOptions::default())
+}
+
+/// Switches for how much of the source is kept in the parsed song.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Keep `//` and `/* */` comments in [`Song::comments`] instead of
+    /// discarding them, e.g. for a formatter that writes the chart back out.
+    pub keep_comments: bool,
+}
+
+pub fn parse_song_with_options(input: &str, options: &ParseOptions) -> ParseResult<Song, Rule> {
+    let mut pairs = LeadSheetMLParser::parse(Rule::song, input)
+        .map_err(|e| LeadSheetMLError::Pest(Box::new(e)))?;
+    let song = pairs.next().ok_or_else(|| internal_error("expected top-level song rule", None, None))?;
+    let comments = if options.keep_comments { collect_comments(song.clone()) } else { Vec::new() };
+    let mut song = parse_song(song, &mut Warnings::strict())?;
+    song.comments = comments;
+    Ok(song)
+}
+
+/// Parses a song and also returns warnings for input the parser accepts but
+/// probably did not mean: unknown chord extensions, directives
+/// that overwrite each other, empty sections and measures missing a bar.
+pub fn parse_song_with_diagnostics(input: &str) -> ParseResult<(Song, Vec<Diagnostic>), Rule> {
     let mut pairs = LeadSheetMLParser::parse(Rule::song, input)
         .map_err(|e| LeadSheetMLError::Pest(Box::new(e)))?;
-    let song = pairs.next().ok_or_else(|| internal_error("expected top-level song rule", None, None));
-    parse_song(song?)
+    let song = pairs.next().ok_or_else(|| internal_error("expected top-level song rule", None, None))?;
+    let mut warnings = Warnings::default();
+    let song = parse_song(song, &mut warnings)?;
+    let Warnings { mut diagnostics, locations, .. } = warnings;
+    diagnostics.extend(check_navigation(&song, &locations));
+    diagnostics.extend(check_form(&song, &locations));
+    diagnostics.extend(check_beats(&song, &locations));
+    Ok((song, diagnostics))
+}
+
+/// Parses as much of a song as possible. Every line that fails to parse is
+/// reported as an error and skipped, so one typo does not lose the rest of
+/// the chart; the returned song holds whatever parsed cleanly. A section
+/// whose header fails is skipped as a whole.
+pub fn parse_song_recovering(input: &str) -> (Song, Vec<Diagnostic>) {
+    let mut source = input.to_string();
+    let mut errors = Vec::new();
+    let mut sectioned = false;
+    loop {
+        match parse_song_with_diagnostics(&source) {
+            Ok((song, mut diagnostics)) => {
+                diagnostics.append(&mut errors);
+                diagnostics.sort_by_key(|d| d.span.map(|s| s.start));
+                return (song, diagnostics);
+            }
+            Err(LeadSheetMLError::Pest(_)) if !sectioned => {
+                // Look for the bad lines one section at a time, so each one
+                // costs a parse of its own section rather than the whole song.
+                sectioned = true;
+                for section in section_ranges(&source) {
+                    let mut text = source[section.clone()].to_string();
+                    while let Err(error) = LeadSheetMLParser::parse(Rule::song, &text) {
+                        let (mut diagnostic, skipped) = skip_failed_line(&mut text, &LeadSheetMLError::Pest(Box::new(error)));
+                        if !skipped {
+                            break;
+                        }
+                        diagnostic.span = diagnostic.span.map(|span| SourceSpan {
+                            start: span.start + section.start,
+                            end: span.end + section.start,
+                        });
+                        errors.push(diagnostic);
+                    }
+                    source.replace_range(section, &text);
+                }
+            }
+            Err(error) => {
+                let (diagnostic, skipped) = skip_failed_line(&mut source, &error);
+                errors.push(diagnostic);
+                if !skipped {
+                    return (Song::default(), errors);
+                }
+            }
+        }
+    }
+}
+
+/// Where each section of `source` starts and ends. The first one also takes
+/// in the directives above it, so every range parses as a song of its own.
+fn section_ranges(source: &str) -> Vec<Range<usize>> {
+    let mut starts = vec![0];
+    let mut seen_header = false;
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        if is_section_header(line) {
+            if seen_header {
+                starts.push(offset);
+            }
+            seen_header = true;
+        }
+        offset += line.len();
+    }
+    starts.push(source.len());
+    starts.windows(2).map(|pair| pair[0]..pair[1]).collect()
+}
+
+fn is_section_header(line: &str) -> bool {
+    line.trim_start().starts_with('#')
+}
+
+/// Blanks the source at fault for an error from parsing `source` and
+/// describes the error. Returns whether anything was blanked, i.e. whether
+/// parsing again can get further.
+fn skip_failed_line(source: &mut String, error: &LeadSheetMLError<Rule>) -> (Diagnostic, bool) {
+    let mut diagnostic = error_to_diagnostic(error, source);
+    let fault = match (error, diagnostic.span) {
+        (LeadSheetMLError::Pest(_) | LeadSheetMLError::Syntax { .. }, Some(span)) => blank_failed_line(source, span.start),
+        _ => None,
+    };
+    if let (LeadSheetMLError::Pest(_), Some(fault), Some(span)) = (error, fault, diagnostic.span)
+        && fault != span.start {
+        diagnostic.message = "Chord bracket '[' is never closed".to_string();
+        diagnostic.span = Some(SourceSpan { start: fault, end: fault + 1 });
+    }
+    (diagnostic, fault.is_some())
+}
+
+/// Replaces the line at fault for an error at `position` with spaces, keeping
+/// byte offsets intact for later spans. When the error sits at the start of a
+/// line right after an unfinished `[`, the earlier line is the one blanked.
+/// A section header is blanked along with the rest of its section, so its
+/// lines are not taken for part of the section before.
+/// Returns where the fault was found, or `None` if there was nothing to blank.
+fn blank_failed_line(source: &mut String, position: usize) -> Option<usize> {
+    let mut fault = position.min(source.len());
+    let before = source[..fault].trim_end();
+    let mut line_start = source[..fault].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    if before.len() < line_start && before.ends_with('[') {
+        fault = before.len() - 1;
+        line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    }
+    let line_end = source[line_start..].find('\n').map(|i| line_start + i).unwrap_or(source.len());
+    let line = &source[line_start..line_end];
+    if line.trim().is_empty() {
+        return None;
+    }
+    let mut end = line_end;
+    if is_section_header(line) {
+        end += source[line_end..]
+            .split_inclusive('\n')
+            .take_while(|next| !is_section_header(next))
+            .map(str::len)
+            .sum::<usize>();
+    }
+    let blank: String = source[line_start..end].bytes().map(|b| if b == b'\n' { '\n' } else { ' ' }).collect();
+    source.replace_range(line_start..end, &blank);
+    Some(fault)
+}
+
+fn collect_comments(unparsed_song: Pair<Rule>) -> Vec<Comment> {
+    unparsed_song
+        .into_inner()
+        .flatten()
+        .filter(|pair| pair.as_rule() == Rule::COMMENT)
+        .map(|pair| {
+            let span = Span::from_pest_span(pair.as_span());
+            let text = pair.as_str();
+            match text.strip_prefix("//") {
+                Some(line) => Comment { kind: CommentKind::Line, text: line.trim().to_string(), span },
+                None => Comment {
+                    kind: CommentKind::Block,
+                    text: text[2..text.len() - 2].trim().to_string(),
+                    span,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Warnings for input the parser accepts but the author probably did not
+/// mean, collected while the song is parsed.
+#[derive(Debug, Default)]
+pub struct Warnings {
+    /// Fail on empty sections, measures missing their closing bar and song-wide
+    /// directives inside a section instead of warning about them. The grammar
+    /// only accepts these so they can be reported; `parse_song_from_str` still
+    /// rejects them.
+    pub strict: bool,
+    pub diagnostics: Vec<Diagnostic>,
+    locations: Locations,
+}
+
+/// Where parts of the song were written, recorded while parsing so the checks
+/// that run on the finished song can point at them, with or without the
+/// `spans` feature.
+#[derive(Debug, Default)]
+struct Locations {
+    form: Option<SourceSpan>,
+    blocks: Vec<BlockLocations>,
+}
+
+/// Where a section, and each of its measures and markers in order, were written.
+#[derive(Debug, Default, Clone)]
+struct BlockLocations {
+    header: Option<SourceSpan>,
+    measures: Vec<SourceSpan>,
+    markers: Vec<SourceSpan>,
+}
+
+impl Locations {
+    fn block(&self, index: usize) -> Option<&BlockLocations> {
+        self.blocks.get(index)
+    }
+}
+
+impl Warnings {
+    pub fn strict() -> Self {
+        Warnings { strict: true, ..Warnings::default() }
+    }
+
+    fn push(&mut self, message: impl Into<String>, code: &str, span: Option<SourceSpan>) {
+        self.diagnostics.push(Diagnostic::warning(message, code, span));
+    }
+
+    /// Warns about input that is a syntax error when parsing strictly.
+    fn reject(&mut self, message: impl Into<String>, code: &str, rule: Rule, span: Option<SourceSpan>) -> ParseResult<(), Rule> {
+        if self.strict {
+            return Err(LeadSheetMLError::Syntax { message: message.into(), rule: Some(rule), span });
+        }
+        self.push(message, code, span);
+        Ok(())
+    }
+}
+
+/// The children of `pair`, without the comments pest leaves in the tree.
+fn children(pair: Pair<Rule>) -> impl Iterator<Item = Pair<Rule>> {
+    pair.into_inner().filter(|p| p.as_rule() != Rule::COMMENT)
+}
+
+/// Checks that every section named in `@form` exists.
+fn check_form(song: &Song, locations: &Locations) -> Vec<Diagnostic> {
+    let Some(form) = song.directives.form() else {
+        return Vec::new();
+    };
+    let span = locations.form;
+    form.entries
+        .iter()
+        .filter(|entry| !song.blocks.iter().any(|block| block.is_named(&entry.section)))
+        .map(|entry| Diagnostic::warning(
+            format!("@form refers to section '{}', which the song does not have", entry.section),
+            codes::UNKNOWN_FORM_SECTION,
+            span))
+        .collect()
+}
+
+/// Checks that every jump has somewhere to go: a D.S. needs a segno, an
+/// `al Coda` jump or "To Coda" needs a coda, and an `al Fine` jump a Fine.
+fn check_navigation(song: &Song, locations: &Locations) -> Vec<Diagnostic> {
+    let markers: Vec<(NavigationMarker, Option<SourceSpan>)> = song
+        .blocks
+        .iter()
+        .enumerate()
+        .flat_map(|(index, block)| {
+            let spans = locations.block(index).map_or(&[][..], |b| b.markers.as_slice());
+            block
+                .lines
+                .iter()
+                .flat_map(|line| &line.segments)
+                .flat_map(|segment| segment.items())
+                .filter_map(|item| match item {
+                    ChordOrText::Marker(marker, _) => Some(*marker),
+                    _ => None,
+                })
+                .enumerate()
+                .map(|(n, marker)| (marker, spans.get(n).copied()))
+        })
+        .collect();
+    let has = |wanted: NavigationMarker| markers.iter().any(|(marker, _)| *marker == wanted);
+
+    let mut diagnostics = Vec::new();
+    for (marker, span) in &markers {
+        let needs = match marker {
+            NavigationMarker::DalSegno(end) => vec![Some(NavigationMarker::Segno), jump_target(*end)],
+            NavigationMarker::DaCapo(end) => vec![jump_target(*end)],
+            NavigationMarker::ToCoda => vec![Some(NavigationMarker::Coda)],
+            _ => Vec::new(),
+        };
+        for needed in needs.into_iter().flatten().filter(|needed| !has(*needed)) {
+            diagnostics.push(Diagnostic::warning(
+                format!("{} has no matching {{{}}}", marker.name(), needed.name()),
+                codes::UNMATCHED_NAVIGATION,
+                *span));
+        }
+    }
+    diagnostics
+}
+
+/// Checks that measures which say how long their chords last fill the bar
+/// exactly, and that no measure has more chords than beats. Each measure is
+/// held to the `@time` in effect where it is written; songs without one are
+/// taken to be in 4/4.
+fn check_beats(song: &Song, locations: &Locations) -> Vec<Diagnostic> {
+    let mut time = song.directives.time().unwrap_or(TimeSignature { beats: 4, unit: 4 });
+    let mut diagnostics = Vec::new();
+    for (block_index, block) in song.blocks.iter().enumerate() {
+        let mut spans = locations.block(block_index).map_or(&[][..], |b| b.measures.as_slice()).iter();
+        for index in 0..=block.lines.len() {
+            for directive in block.directives_at(index).filter(|d| d.name == "time") {
+                if let Ok(changed) = directive.value.parse() {
+                    time = changed;
+                }
+            }
+            if let Some(line) = block.lines.get(index) {
+                for segment in &line.segments {
+                    let Segment::Measure(measure) = segment else {
+                        continue;
+                    };
+                    diagnostics.extend(check_measure_beats(measure, &time, spans.next().copied()));
+                }
+            }
+        }
+    }
+    diagnostics
+}
+
+fn check_measure_beats(measure: &Measure, time: &TimeSignature, span: Option<SourceSpan>) -> Option<Diagnostic> {
+    let positions = measure.beats(time);
+    let total = positions.iter().fold(0, |total: u32, p| total.saturating_add(p.length));
+    // A pickup only has to fit in the bar.
+    let mismatched = if measure.pickup { total > time.beats } else { total != time.beats };
+    let message = if measure.has_written_beats() && mismatched {
+        format!("Measure has {} beats, but {} time has {}", total, time, time.beats)
+    } else if positions.len() as u32 > time.beats {
+        format!("Measure has {} chords, more than the {} beats of {} time", positions.len(), time.beats, time)
+    } else {
+        return None;
+    };
+    Some(Diagnostic::warning(message, codes::BEAT_MISMATCH, span))
+}
+
+fn jump_target(end: JumpEnd) -> Option<NavigationMarker> {
+    match end {
+        JumpEnd::End => None,
+        JumpEnd::Fine => Some(NavigationMarker::Fine),
+        JumpEnd::Coda => Some(NavigationMarker::Coda),
+    }
+}
+
+fn check_directive(directive: &Directive, span: Option<SourceSpan>, warnings: &mut Warnings) {
+    if let Err(error) = validate_directive(directive) {
+        warnings.push(format!("Invalid @{} directive: {}", directive.name, error), codes::INVALID_DIRECTIVE_VALUE, span);
+    }
 }
 
-pub fn parse_song(unparsed_song: pest::iterators::Pair<Rule>) -> ParseResult<Song, Rule> {
-    let mut directives:HashMap<String, String> =  HashMap::new();
+/// Checks that a standard directive holds a value of the right type. Other
+/// directives are free-form text and always pass.
+fn validate_directive(directive: &Directive) -> Result<(), MetadataError> {
+    let value = directive.value.as_str();
+    match directive.name.as_str() {
+        "key" => value.parse::<Key>().map(drop),
+        "time" => value.parse::<TimeSignature>().map(drop),
+        "tempo" => value.parse::<Tempo>().map(drop),
+        "form" => value.parse::<Form>().map(drop),
+        "capo" => value.trim().parse::<usize>().map(drop).map_err(|_| MetadataError {
+            message: format!("'{}' is not a fret number", value),
+        }),
+        _ => Ok(()),
+    }
+}
+
+pub fn parse_song(unparsed_song: pest::iterators::Pair<Rule>, warnings: &mut Warnings) -> ParseResult<Song, Rule> {
+    let span = Span::from_pest_span(unparsed_song.as_span());
+    let mut directives = Metadata::new();
     let mut blocks:Vec<Block> = Vec::new();
-    for song_elements in unparsed_song.into_inner() {
+    for song_elements in children(unparsed_song) {
         match song_elements.as_rule() {
             Rule::directive_list => {
-                for unparsed_directive in song_elements.into_inner() {
+                for unparsed_directive in children(song_elements) {
+                    let directive_span = Some(SourceSpan::from_pest_span(unparsed_directive.as_span()));
                     let directive = parse_directive(unparsed_directive)?;
-                    directives.insert(directive.name, directive.value);
+                    if directives.get(&directive.name).is_some()
+                        && SINGLE_VALUED_DIRECTIVES.contains(&directive.name.as_str()) {
+                        warnings.push(
+                            format!("Duplicate @{} directive is ignored; the first value is used", directive.name),
+                            codes::DUPLICATE_DIRECTIVE,
+                            directive_span);
+                    }
+                    check_directive(&directive, directive_span, warnings);
+                    if directive.name == "form" && warnings.locations.form.is_none() {
+                        warnings.locations.form = directive_span;
+                    }
+                    directives.push(directive);
                 }
             }
             Rule::blocks => {
-                blocks = parse_blocks(song_elements)?;
+                blocks = parse_blocks(song_elements, warnings)?;
             }
             Rule::EOI => {}
             _ => return internal(format!("Unexpected rule: {:?}", song_elements.as_rule()), Some(song_elements.as_rule()), Some(song_elements.as_span()))
@@ -37,11 +411,14 @@ pub fn parse_song(unparsed_song: pest::iterators::Pair<Rule>) -> ParseResult<Son
     }
     Ok(Song {
         directives,
-        blocks
+        blocks,
+        comments: Vec::new(),
+        span
     })
 }
 
 pub fn parse_directive(unparsed_directive: pest::iterators::Pair<Rule>) -> ParseResult<Directive, Rule> {
+    let span = Span::from_pest_span(unparsed_directive.as_span());
     let mut directive_name = "";
     let mut directive_value = "";
     for directive_elements in unparsed_directive.into_inner() {
@@ -50,7 +427,7 @@ pub fn parse_directive(unparsed_directive: pest::iterators::Pair<Rule>) -> Parse
                 directive_name = directive_elements.as_str();
             }
             Rule::value => {
-                directive_value = directive_elements.as_str();
+                directive_value = directive_elements.as_str().trim_end();
             }
             _ => {}
         }
@@ -58,75 +435,296 @@ pub fn parse_directive(unparsed_directive: pest::iterators::Pair<Rule>) -> Parse
     Ok(Directive {
         name: directive_name.to_string(),
         value: directive_value.to_string(),
+        span: Some(span),
     })
 }
 
-pub fn parse_blocks(unparsed_blocks: pest::iterators::Pair<Rule>) -> ParseResult<Vec<Block>, Rule> {
+pub fn parse_blocks(unparsed_blocks: pest::iterators::Pair<Rule>, warnings: &mut Warnings) -> ParseResult<Vec<Block>, Rule> {
     let mut blocks = Vec::new();
-    for block in unparsed_blocks.into_inner() {
-        blocks.push(parse_block(block)?);
+    for block in children(unparsed_blocks) {
+        blocks.push(parse_block(block, warnings)?);
     }
+    resolve_references(&mut blocks, &mut warnings.locations)?;
     Ok(blocks)
 }
 
-pub fn parse_block(unparsed_block: pest::iterators::Pair<Rule>) -> ParseResult<Block, Rule> {
-    let mut section_name = "";
+/// Fills in each `#Name*` section with the lines of the earlier section of
+/// that name and the directives between them. Directives written under the
+/// reference itself come after the copied lines.
+fn resolve_references(blocks: &mut [Block], locations: &mut Locations) -> ParseResult<(), Rule> {
+    for index in 0..blocks.len() {
+        if !blocks[index].is_reference {
+            continue;
+        }
+        let reference = &blocks[index];
+        let span = locations.block(index).and_then(|b| b.header);
+        let error = |message: String| Err(LeadSheetMLError::Syntax {
+            message,
+            rule: Some(Rule::section_header),
+            span,
+        });
+        if !reference.lines.is_empty() {
+            return error(format!("Section reference #{}* cannot have lines of its own", reference.section_name));
+        }
+        match blocks[..index].iter().position(|b| b.is_named(reference.name())) {
+            Some(original_index) => {
+                let original = &blocks[original_index];
+                if let Some(copied) = locations.block(original_index).cloned()
+                    && let Some(own) = locations.blocks.get_mut(index) {
+                    own.measures = copied.measures;
+                    own.markers = copied.markers;
+                }
+                let lines = original.lines.clone();
+                // Directives after the last line lead into the next section,
+                // and a rehearsal mark names only the original.
+                let mut directives: Vec<InlineDirective> = original
+                    .directives
+                    .iter()
+                    .filter(|d| d.line < lines.len() && d.directive.name != "mark")
+                    .cloned()
+                    .collect();
+                for own in blocks[index].directives.drain(..) {
+                    directives.push(InlineDirective { line: lines.len(), ..own });
+                }
+                blocks[index].lines = lines;
+                blocks[index].directives = directives;
+            }
+            None => return error(format!("Section reference #{}* does not match any section above it", reference.section_name)),
+        }
+    }
+    Ok(())
+}
+
+/// Splits a section name into its label, number and repeat count. The repeat
+/// count comes last, as `x2`, `×2` or `(x2)`; a number just before it, like
+/// the 2 in `Verse 2`, is the section number. Everything else is the label.
+pub fn parse_section_name(name: &str) -> SectionName {
+    let mut words: Vec<&str> = name.split_whitespace().collect();
+    let repeat_count = pop_trailing(&mut words, |word| {
+        repeat_count(word.strip_prefix('(').and_then(|w| w.strip_suffix(')')).unwrap_or(word))
+    });
+    let number = pop_trailing(&mut words, |word| word.parse().ok());
+    SectionName { label: words.join(" "), number, repeat_count }
+}
+
+/// Removes the last word if `read` understands it, always leaving the label.
+fn pop_trailing(words: &mut Vec<&str>, read: impl Fn(&str) -> Option<u32>) -> Option<u32> {
+    if words.len() < 2 {
+        return None;
+    }
+    let value = read(words[words.len() - 1])?;
+    words.pop();
+    Some(value)
+}
+
+pub fn parse_block(unparsed_block: pest::iterators::Pair<Rule>, warnings: &mut Warnings) -> ParseResult<Block, Rule> {
+    let span = Span::from_pest_span(unparsed_block.as_span());
+    let block_span = Some(SourceSpan::from_pest_span(unparsed_block.as_span()));
+    warnings.locations.blocks.push(BlockLocations { header: block_span, ..BlockLocations::default() });
+    let block_text = unparsed_block.as_str().trim();
+    let mut section_name = String::new();
+    let mut is_reference = false;
     let mut lines:Vec<LyricLine> = Vec::new();
-    for block_element in unparsed_block.into_inner() {
+    let mut directives = Vec::new();
+    for block_element in children(unparsed_block) {
         match block_element.as_rule() {
             Rule::section_header => {
-                section_name = block_element.as_str();
+                for header_element in children(block_element) {
+                    match header_element.as_rule() {
+                        Rule::section_title => {
+                            section_name = header_element.as_str().split_whitespace().collect::<Vec<&str>>().join(" ");
+                        }
+                        Rule::section_reference => is_reference = true,
+                        _ => {}
+                    }
+                }
             }
             Rule::lyric_line => {
-                lines.push(parse_line(block_element)?)
+                lines.push(parse_line(block_element, warnings)?)
+            }
+            Rule::directive => {
+                let directive_span = Some(SourceSpan::from_pest_span(block_element.as_span()));
+                let directive = parse_directive(block_element)?;
+                let name = directive.name.as_str();
+                // `@title`, `@capo` and `@form` describe the whole song.
+                if SINGLE_VALUED_DIRECTIVES.contains(&name) && !CHANGEABLE_DIRECTIVES.contains(&name) {
+                    warnings.reject(
+                        format!("@{} cannot change partway through a song; it is ignored", name),
+                        codes::MISPLACED_DIRECTIVE,
+                        Rule::directive,
+                        directive_span)?;
+                } else {
+                    check_directive(&directive, directive_span, warnings);
+                    directives.push(InlineDirective { directive, line: lines.len() });
+                }
             }
             _ => return internal(format!("Invalid block elemement: {:?}", block_element.as_rule())
             ,Some(Rule::lyric_line)
             ,Some(block_element.as_span()))
         }
     }
+    if lines.is_empty() && !is_reference {
+        warnings.reject(format!("Section {} is empty", block_text), codes::EMPTY_SECTION, Rule::block, block_span)?;
+    }
     Ok(Block {
-        section_name: section_name.to_string(),
-        lines
+        section: parse_section_name(&section_name),
+        section_name,
+        lines,
+        directives,
+        is_reference,
+        span
     })
 }
 
-pub fn parse_line(unparsed_line: pest::iterators::Pair<Rule>) -> ParseResult<LyricLine, Rule> {
+pub fn parse_line(unparsed_line: pest::iterators::Pair<Rule>, warnings: &mut Warnings) -> ParseResult<LyricLine, Rule> {
+    let span = Span::from_pest_span(unparsed_line.as_span());
     let mut segments:Vec<Segment> = Vec::new();
-    for line in unparsed_line.into_inner() {
+    let mut pickup = false;
+    for line in children(unparsed_line) {
         match line.as_rule() {
-            Rule::measure => {
-                segments.push(parse_measure(line)?)
+            Rule::measures => {
+                segments.extend(parse_measures(line, warnings)?)
             }
             Rule::lyric_block => {
-                segments.push(parse_lyric_block(line)?)
+                pickup = children(line.clone()).next().is_some_and(|p| p.as_rule() == Rule::pickup_token);
+                segments.push(parse_lyric_block(line, warnings)?)
             }
             _ => return internal(format!("Invalid line: {:?}", line.as_rule()),
                                  Some(line.as_rule()),
                                  Some(line.as_span()))
         }
     }
-    Ok(LyricLine{ segments })
+    Ok(LyricLine{ segments, pickup, span })
+}
+
+pub fn parse_measures(unparsed_measures: Pair<Rule>, warnings: &mut Warnings) -> ParseResult<Vec<Segment>, Rule> {
+    let mut measures = Vec::new();
+    let mut last = None;
+    for unparsed_measure in children(unparsed_measures) {
+        last = Some(unparsed_measure.clone());
+        measures.push(parse_measure(unparsed_measure, warnings)?);
+    }
+    if let Some(last) = last
+        && children(last.clone()).last().is_some_and(|p| matches!(p.as_rule(), Rule::chord_or_text | Rule::bare_symbol | Rule::beat_dots)) {
+        warnings.reject(
+            "Measure is missing its closing bar '|'",
+            codes::UNBALANCED_MEASURE,
+            Rule::bar,
+            Some(SourceSpan::from_pest_span(last.as_span())))?;
+    }
+    // A barline shared by two measures also closes the first one.
+    for index in 1..measures.len() {
+        if measures[index - 1].end.is_none() {
+            measures[index - 1].end = Some(measures[index].start);
+        }
+    }
+    Ok(measures.into_iter().map(Segment::Measure).collect())
+}
+
+pub fn parse_measure(unparsed_measure: Pair<Rule>, warnings: &mut Warnings) -> ParseResult<Measure, Rule> {
+    let span = Span::from_pest_span(unparsed_measure.as_span());
+    if let Some(block) = warnings.locations.blocks.last_mut() {
+        block.measures.push(SourceSpan::from_pest_span(unparsed_measure.as_span()));
+    }
+    let measure_span = unparsed_measure.as_span();
+    let mut bars = Vec::new();
+    let mut volta = None;
+    let mut pickup = false;
+    let mut items = Vec::new();
+    let mut repeat_count = None;
+    let mut count_pair = None;
+    for measure_element in children(unparsed_measure) {
+        match measure_element.as_rule() {
+            Rule::bar => bars.push(parse_bar(measure_element)?),
+            Rule::volta => volta = Some(parse_volta(measure_element)?),
+            Rule::pickup_token => pickup = true,
+            Rule::chord_or_text => items.push(parse_chords_or_text(measure_element, warnings)?),
+            Rule::bare_symbol => items.push(parse_chart_symbol(measure_element)?),
+            Rule::beat_dots => match items.last_mut() {
+                Some(ChordOrText::Chord(chord)) => chord.duration = Some(measure_element.as_str().len() as u32),
+                _ => return syntax("Beat dots like '..' must follow a chord", Some(Rule::beat_dots), Some(measure_element.as_span())),
+            },
+            Rule::repeat_count => {
+                repeat_count = Some(parse_repeat_count(measure_element.clone())?);
+                count_pair = Some(measure_element);
+            }
+            _ => return internal(format!("Invalid measure element: {:?}", measure_element.as_rule()),
+                                 Some(Rule::measure),
+                                 Some(measure_element.as_span()))
+        }
+    }
+    let mut bars = bars.into_iter();
+    let start = match bars.next() {
+        Some(bar) => bar,
+        None => return internal("Measure has no opening bar".to_string(), Some(Rule::measure), Some(measure_span))
+    };
+    let end = bars.next();
+    if let Some(count) = count_pair
+        && !end.is_some_and(Barline::ends_repeat) {
+        warnings.push(
+            format!("Repeat count '{}' is ignored; it must follow a closing repeat ':|'", count.as_str()),
+            codes::MISPLACED_REPEAT_COUNT,
+            Some(SourceSpan::from_pest_span(count.as_span())));
+    }
+    Ok(Measure {
+        start,
+        volta,
+        pickup,
+        items,
+        end,
+        repeat_count,
+        span
+    })
+}
+
+pub fn parse_bar(unparsed_bar: Pair<Rule>) -> ParseResult<Barline, Rule> {
+    let bar = unparsed_bar.as_str();
+    match bar {
+        "|" => Ok(Barline::Single),
+        "||" => Ok(Barline::Double),
+        "|]" => Ok(Barline::Final),
+        "|:" | "||:" => Ok(Barline::StartRepeat),
+        ":|" | ":||" => Ok(Barline::EndRepeat),
+        ":|:" | ":||:" => Ok(Barline::EndStartRepeat),
+        _ => syntax(format!("Invalid barline: {}", bar), Some(Rule::bar), unparsed_bar.as_span().into())
+    }
+}
+
+pub fn parse_volta(unparsed_volta: Pair<Rule>) -> ParseResult<Volta, Rule> {
+    let text = unparsed_volta.as_str();
+    let mut endings = Vec::new();
+    for ending in text.trim_end_matches('.').split(',') {
+        match ending.parse() {
+            Ok(ending) => endings.push(ending),
+            Err(_) => return syntax(format!("Invalid ending: {}", text), Some(Rule::volta), unparsed_volta.as_span().into())
+        }
+    }
+    Ok(Volta { endings })
 }
 
-pub fn parse_measure(unparsed_measure: Pair<Rule>) -> ParseResult<Segment, Rule> {
-    let chords_or_text = parse_line_lyric(unparsed_measure)?;
-    Ok(Segment::Measure(chords_or_text))
+pub fn parse_repeat_count(unparsed_count: Pair<Rule>) -> ParseResult<u32, Rule> {
+    let text = unparsed_count.as_str();
+    match text[1..].parse() {
+        Ok(count) => Ok(count),
+        Err(_) => syntax(format!("Invalid repeat count: {}", text), Some(Rule::repeat_count), unparsed_count.as_span().into())
+    }
 }
 
-pub fn parse_lyric_block(unparsed_lyric_block: pest::iterators::Pair<Rule>) -> ParseResult<Segment, Rule> {
-    let chords_or_text = parse_line_lyric(unparsed_lyric_block)?;
-    Ok(Segment::Inline(chords_or_text))
+pub fn parse_lyric_block(unparsed_lyric_block: pest::iterators::Pair<Rule>, warnings: &mut Warnings) -> ParseResult<Segment, Rule> {
+    let span = Span::from_pest_span(unparsed_lyric_block.as_span());
+    let chords_or_text = parse_line_lyric(unparsed_lyric_block, warnings)?;
+    Ok(Segment::Inline(chords_or_text, span))
 }
 
-pub fn parse_line_lyric(unparsed_measure: pest::iterators::Pair<Rule>) -> ParseResult<Vec<ChordOrText>, Rule> {
+pub fn parse_line_lyric(unparsed_measure: pest::iterators::Pair<Rule>, warnings: &mut Warnings) -> ParseResult<Vec<ChordOrText>, Rule> {
     let mut chords_or_text:Vec<ChordOrText> = Vec::new();
-    for measure_element in unparsed_measure.into_inner() {
+    for measure_element in children(unparsed_measure) {
         match measure_element.as_rule() {
             Rule::chord_or_text => {
-                let chord_or_text = parse_chords_or_text(measure_element);
+                let chord_or_text = parse_chords_or_text(measure_element, warnings);
                 chords_or_text.push(chord_or_text?);
             }
+            Rule::pickup_token => {}
             _ => return internal("Invalid lyric line".to_string(), Some(Rule::lyric_line), Some(measure_element.as_span()))
         }
     }
@@ -134,53 +732,126 @@ pub fn parse_line_lyric(unparsed_measure: pest::iterators::Pair<Rule>) -> ParseR
 }
 
 
-pub fn parse_chords_or_text(pair: Pair<Rule>) -> ParseResult<ChordOrText, Rule> {
-    let mut inner = pair.into_inner();
+pub fn parse_chords_or_text(pair: Pair<Rule>, warnings: &mut Warnings) -> ParseResult<ChordOrText, Rule> {
+    let mut inner = children(pair);
 
     let first = inner
         .next()
         .ok_or_else(|| internal_error("Chord or text token has no inner elements", None, None))?;
 
     match first.as_rule() {
-        Rule::chord_token => Ok(ChordOrText::Chord(parse_chord_token(first)?)),
+        Rule::chord_token => match children(first.clone()).next() {
+            Some(symbol) if symbol.as_rule() == Rule::chart_symbol => parse_chart_symbol(symbol),
+            _ => Ok(ChordOrText::Chord(parse_chord_token(first, warnings)?)),
+        },
+        Rule::marker_token => {
+            if let Some(block) = warnings.locations.blocks.last_mut() {
+                block.markers.push(SourceSpan::from_pest_span(first.as_span()));
+            }
+            parse_marker_token(first)
+        }
         Rule::text_token => parse_text_token(first),
         _ => internal(format!("Invalid chord or text token: {:?}", first.as_rule()), None, None),
     }
 }
 
 pub fn parse_text_token(unparsed_text_token: pest::iterators::Pair<Rule>) -> ParseResult<ChordOrText, Rule> {
+    let span = Span::from_pest_span(unparsed_text_token.as_span());
     let text = unparsed_text_token.as_str().to_string();
-    Ok(ChordOrText::Text(text))
+    Ok(ChordOrText::Text(text, span))
 }
 
-pub fn parse_chord_token(unparsed_chord: pest::iterators::Pair<Rule>) -> ParseResult<Chord, Rule> {
-    let chord = unparsed_chord
-        .into_inner()
+pub fn parse_marker_token(unparsed_marker_token: Pair<Rule>) -> ParseResult<ChordOrText, Rule> {
+    let span = Span::from_pest_span(unparsed_marker_token.as_span());
+    let marker_span = unparsed_marker_token.as_span();
+    let marker = match children(unparsed_marker_token).next() {
+        Some(marker) => marker,
+        None => return internal("Marker token has no inner elements".to_string(), Some(Rule::marker_token), Some(marker_span))
+    };
+    Ok(ChordOrText::Marker(parse_marker(marker)?, span))
+}
+
+pub fn parse_marker(unparsed_marker: Pair<Rule>) -> ParseResult<NavigationMarker, Rule> {
+    let marker = unparsed_marker.as_str().to_ascii_lowercase();
+    let words: Vec<&str> = marker.split_whitespace().collect();
+    let jump_end = match words.as_slice() {
+        [_] => JumpEnd::End,
+        [_, "al", "fine"] => JumpEnd::Fine,
+        [_, "al", "coda"] => JumpEnd::Coda,
+        _ => JumpEnd::End,
+    };
+    match words.first().copied() {
+        Some("segno") => Ok(NavigationMarker::Segno),
+        Some("coda") => Ok(NavigationMarker::Coda),
+        Some("to") => Ok(NavigationMarker::ToCoda),
+        Some("fine") => Ok(NavigationMarker::Fine),
+        Some("d.c.") => Ok(NavigationMarker::DaCapo(jump_end)),
+        Some("d.s.") => Ok(NavigationMarker::DalSegno(jump_end)),
+        _ => syntax(format!("Invalid navigation marker: {}", marker), Some(Rule::marker), unparsed_marker.as_span().into())
+    }
+}
+
+/// Reads `N.C.`, `%`, `/` or a rest, written in brackets or, inside a
+/// measure, on their own.
+pub fn parse_chart_symbol(unparsed_symbol: Pair<Rule>) -> ParseResult<ChordOrText, Rule> {
+    let span = Span::from_pest_span(unparsed_symbol.as_span());
+    let symbol_span = unparsed_symbol.as_span();
+    match children(unparsed_symbol).next().map(|p| p.as_rule()) {
+        Some(Rule::no_chord) => Ok(ChordOrText::NoChord(span)),
+        Some(Rule::simile) => Ok(ChordOrText::RepeatMeasure(span)),
+        Some(Rule::beat_slash) => Ok(ChordOrText::Slash(span)),
+        Some(Rule::rest) => Ok(ChordOrText::Rest(span)),
+        _ => internal("Chart symbol has no inner elements".to_string(), Some(Rule::chart_symbol), Some(symbol_span))
+    }
+}
+
+pub fn parse_chord_token(unparsed_chord: pest::iterators::Pair<Rule>, warnings: &mut Warnings) -> ParseResult<Chord, Rule> {
+    let mut elements = children(unparsed_chord);
+    let chord = elements
         .next()
         .ok_or_else(|| LeadSheetMLError::Internal{
             message: "Chord token has no inner elements".to_string(),
             rule: None,
             span: None,
         })?;
-    let parsed_chord = parse_chord(chord)?;
+    let mut parsed_chord = parse_chord(chord, warnings)?;
+    if let Some(duration) = elements.next() {
+        parsed_chord.duration = Some(parse_duration(duration)?);
+    }
     Ok(parsed_chord)
 }
 
-pub fn parse_chord(unparsed_chord: pest::iterators::Pair<Rule>) -> ParseResult<Chord, Rule> {
+/// Reads the `:2` of `[C:2]`.
+pub fn parse_duration(unparsed_duration: Pair<Rule>) -> ParseResult<u32, Rule> {
+    let duration = unparsed_duration.as_str();
+    match duration.trim_start_matches(':').parse::<u32>() {
+        Ok(beats) if (1..=TimeSignature::MAX_BEATS).contains(&beats) => Ok(beats),
+        Ok(0) => syntax(format!("A chord must last at least one beat, not '{}'", duration), Some(Rule::duration), Some(unparsed_duration.as_span())),
+        _ => syntax(
+            format!("A chord can last at most {} beats, not '{}'", TimeSignature::MAX_BEATS, duration.trim_start_matches(':')),
+            Some(Rule::duration),
+            Some(unparsed_duration.as_span())),
+    }
+}
+
+pub fn parse_chord(unparsed_chord: pest::iterators::Pair<Rule>, warnings: &mut Warnings) -> ParseResult<Chord, Rule> {
+    let span = Span::from_pest_span(unparsed_chord.as_span());
     let mut chord = Chord {
         root: Note {
             letter: NoteLetter::A,
             accidental: Accidental::None,
         },
-        inversion: None,
-        quality: None,
-        extensions: Vec::new(),
-        bass: None
+        quality: Quality::Major,
+        extension: None,
+        alterations: Vec::new(),
+        bass: None,
+        duration: None,
+        span
     };
-    for chord_element in unparsed_chord.into_inner() {
+    for chord_element in children(unparsed_chord) {
         match chord_element.as_rule() {
             Rule::chord_elements=> {
-                chord = parse_chord_element(chord_element)?;
+                chord = parse_chord_element(chord_element, warnings)?;
             }
             Rule::slash_chord => {
                 let slash_chord_note = parse_slash_chord(chord_element)?;
@@ -189,14 +860,13 @@ pub fn parse_chord(unparsed_chord: pest::iterators::Pair<Rule>) -> ParseResult<C
             _ => return internal(format!("Invalid chord element: {:?}", chord_element.as_rule()), None, None)
         }
     }
+    chord.span = span;
     Ok(chord)
 }
 
 pub fn parse_slash_chord(unparsed_slash_chord_note: Pair<Rule>) -> ParseResult<Option<Note>, Rule> {
-    let slash_chord = match unparsed_slash_chord_note
-        .into_inner()
-        .skip(1) //Skip the slash
-        .next() {
+    let slash_chord = match children(unparsed_slash_chord_note)
+        .nth(1) { //Skip the slash
         Some(pair) => pair,
         None => return internal("Slash chord has no inner elements".to_string(), None, None)
     };
@@ -205,36 +875,69 @@ pub fn parse_slash_chord(unparsed_slash_chord_note: Pair<Rule>) -> ParseResult<O
 }
 
 
-pub fn parse_chord_element(unparsed_chord_elements: Pair<Rule>) -> ParseResult<Chord, Rule> {
+pub fn parse_chord_element(unparsed_chord_elements: Pair<Rule>, warnings: &mut Warnings) -> ParseResult<Chord, Rule> {
+    let span = Span::from_pest_span(unparsed_chord_elements.as_span());
     let mut root =  Note {
         letter: NoteLetter::A,
         accidental: Accidental::None,
     };
-    let mut quality:Option<String> = None;
-    let mut extensions:Vec<Option<String>> = Vec::new();
-    let inversion:Option<String> = None;
+    let mut quality = Quality::Major;
+    let mut quality_text = "";
+    let mut suffixes: Vec<ChordSuffix> = Vec::new();
 
-    for chord_element in unparsed_chord_elements.into_inner() {
+    for chord_element in children(unparsed_chord_elements) {
         match chord_element.as_rule() {
             Rule::key => {
                 root = parse_note(chord_element)?
             }
             Rule::quality => {
+                quality_text = chord_element.as_str();
                 quality = parse_quality(chord_element)?;
             }
-            Rule::extension => {
-                extensions.push(parse_extension(chord_element)?)
+            Rule::extension => suffixes.extend(read_extension(chord_element, warnings)?),
+            Rule::tensions => {
+                for tension in children(chord_element) {
+                    suffixes.extend(read_extension(tension, warnings)?);
+                }
             }
             _ => return internal(format!("Invalid chord element: {:?}", chord_element.as_rule()), None, None)
 
         }
     }
+
+    // `maj`, `M` and `Δ` make a following 7, 9 or 13 a major seventh; `Δ`
+    // and `ø` on their own already mean a seventh chord.
+    let major_seventh = matches!(quality_text, "maj" | "Maj" | "M" | "Δ" | "^");
+    let mut extension = matches!(quality_text, "Δ" | "^" | "ø")
+        .then_some(Extension { degree: 7, major_seventh });
+    let mut alterations: Vec<Alteration> = Vec::new();
+    for suffix in suffixes {
+        match suffix {
+            ChordSuffix::Extension(added) => {
+                extension = Some(Extension {
+                    degree: extension.map_or(added.degree, |e| e.degree.max(added.degree)),
+                    major_seventh: major_seventh || added.major_seventh || extension.is_some_and(|e| e.major_seventh),
+                });
+            }
+            ChordSuffix::Quality(suffix) => quality = suffix,
+            ChordSuffix::Alteration(alteration) => alterations.push(alteration),
+        }
+    }
+    // `m7b5` is the usual spelling of a half-diminished chord.
+    if quality == Quality::Minor
+        && extension.is_some_and(|e| !e.major_seventh)
+        && let Some(flat_five) = alterations.iter().position(|a| *a == Alteration::Flat(5)) {
+        alterations.remove(flat_five);
+        quality = Quality::HalfDiminished;
+    }
     Ok(Chord {
         root,
-        inversion,
         quality,
-        extensions,
-        bass: None
+        extension,
+        alterations,
+        bass: None,
+        duration: None,
+        span
     })
 }
 
@@ -243,7 +946,7 @@ pub fn parse_note(unparsed_note: Pair<Rule>) -> ParseResult<Note, Rule> {
         letter: NoteLetter::A,
         accidental: Accidental::None
     };
-    for note_element in unparsed_note.into_inner(){
+    for note_element in children(unparsed_note){
         match note_element.as_rule() {
             Rule::note => {
                 note.letter = parse_letter(note_element)?
@@ -288,43 +991,59 @@ pub fn parse_accidental(unparsed_accidental: pest::iterators::Pair<Rule>) -> Par
     }
 }
 
-pub fn parse_quality(unparsed_quality: pest::iterators::Pair<Rule>) -> ParseResult<Option<String>, Rule> {
+pub fn parse_quality(unparsed_quality: pest::iterators::Pair<Rule>) -> ParseResult<Quality, Rule> {
     let quality = unparsed_quality.as_str();
     match quality {
-        "maj" => Ok(Some("maj".to_string())),
-        "min" => Ok(Some("min".to_string())),
-        "dim" => Ok(Some("dim".to_string())),
-        "aug" => Ok(Some("aug".to_string())),
-        "m" => Ok(Some("m".to_string())),
-        "+" => Ok(Some("+".to_string())),
-        _ => Ok(None)
+        "maj" | "Maj" | "M" | "Δ" | "^" => Ok(Quality::Major),
+        "min" | "mi" | "m" | "-" => Ok(Quality::Minor),
+        "dim" | "°" | "o" => Ok(Quality::Diminished),
+        "aug" | "+" => Ok(Quality::Augmented),
+        "ø" => Ok(Quality::HalfDiminished),
+        _ => internal(format!("Invalid chord quality: {}", quality), Some(Rule::quality), Some(unparsed_quality.as_span()))
     }
 }
-pub fn parse_extension(unparsed_extension: pest::iterators::Pair<Rule>) -> ParseResult<Option<String>, Rule> {
-    let extension = unparsed_extension.as_str();
-    match extension {
-        "7" => Ok(Some("7".to_string())),
-        "9" => Ok(Some("9".to_string())),
-        "maj7" => Ok(Some("maj7".to_string())),
-        "maj9" => Ok(Some("maj9".to_string())),
-        "min7" => Ok(Some("min7".to_string())),
-        "min9" => Ok(Some("min9".to_string())),
-        "11" => Ok(Some("11".to_string())),
-        "13" => Ok(Some("13".to_string())),
-        "b5" => Ok(Some("b5".to_string())),
-        "b9" => Ok(Some("b9".to_string())),
-        "b11" => Ok(Some("b11".to_string())),
-        "b13" => Ok(Some("b13".to_string())),
-        "#5" => Ok(Some("#5".to_string())),
-        "#9" => Ok(Some("#9".to_string())),
-        "#11" => Ok(Some("#11".to_string())),
-        "#13" => Ok(Some("#13".to_string())),
-        "dim7" => Ok(Some("dim7".to_string())),
-        "dim9" => Ok(Some("dim9".to_string())),
-        "sus2" => Ok(Some("sus2".to_string())),
-        "sus4" => Ok(Some("sus4".to_string())),
-        "dim" => Ok(Some("dim".to_string())),
-        "aug" => Ok(Some("aug".to_string())),
-        _ => Ok(None)
+
+/// What one `extension` token adds to a chord.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChordSuffix {
+    /// `7`, `9`, `11` or `13`, or `maj7` and the like after a minor quality.
+    Extension(Extension),
+    /// `5`, `sus2` and `sus4` replace the triad.
+    Quality(Quality),
+    Alteration(Alteration),
+}
+
+/// Reads an extension, warning about one the chord model has no place for.
+fn read_extension(unparsed_extension: Pair<Rule>, warnings: &mut Warnings) -> ParseResult<Option<ChordSuffix>, Rule> {
+    let text = unparsed_extension.as_str();
+    let span = Some(SourceSpan::from_pest_span(unparsed_extension.as_span()));
+    let suffix = parse_extension(unparsed_extension)?;
+    if suffix.is_none() {
+        warnings.push(format!("Unknown chord extension '{}' is ignored", text), codes::UNKNOWN_EXTENSION, span);
     }
+    Ok(suffix)
+}
+
+pub fn parse_extension(unparsed_extension: pest::iterators::Pair<Rule>) -> ParseResult<Option<ChordSuffix>, Rule> {
+    let extension = unparsed_extension.as_str();
+    let tone = children(unparsed_extension)
+        .find(|p| p.as_rule() == Rule::tone)
+        .and_then(|p| p.as_str().parse::<u8>().ok());
+    let prefix = tone.map_or(extension, |tone| extension.trim_end_matches(tone.to_string().as_str()));
+    let suffix = match (prefix, tone) {
+        ("6/9" | "69", None) => ChordSuffix::Alteration(Alteration::SixNine),
+        ("alt", None) => ChordSuffix::Alteration(Alteration::Altered),
+        ("sus", None) | ("sus4", None) => ChordSuffix::Quality(Quality::Suspended(4)),
+        ("sus2", None) => ChordSuffix::Quality(Quality::Suspended(2)),
+        ("", Some(degree @ (7 | 9 | 11 | 13))) => ChordSuffix::Extension(Extension { degree, major_seventh: false }),
+        ("maj" | "Maj" | "M" | "Δ" | "^", Some(degree @ (7 | 9 | 11 | 13))) => ChordSuffix::Extension(Extension { degree, major_seventh: true }),
+        ("", Some(6)) => ChordSuffix::Alteration(Alteration::Sixth),
+        ("", Some(5)) => ChordSuffix::Quality(Quality::Power),
+        ("", Some(tone @ (2 | 4))) | ("add", Some(tone @ (2 | 4 | 9 | 11 | 13))) => ChordSuffix::Alteration(Alteration::Add(tone)),
+        ("omit" | "no", Some(tone @ (1 | 3 | 5))) => ChordSuffix::Alteration(Alteration::Omit(tone)),
+        ("b", Some(tone @ (5 | 6 | 9 | 11 | 13))) => ChordSuffix::Alteration(Alteration::Flat(tone)),
+        ("#", Some(tone @ (5 | 9 | 11 | 13))) => ChordSuffix::Alteration(Alteration::Sharp(tone)),
+        _ => return Ok(None)
+    };
+    Ok(Some(suffix))
 }
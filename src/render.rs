@@ -1,113 +1,394 @@
 use crate::ast::*;
+use crate::transpose;
 use markup_engine::engine::MarkupEngine;
 use std::fmt::Write;
+use unicode_width::UnicodeWidthStr;
+
+/// Settings that change how a song is laid out without changing the song.
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    /// Capo fret to render chord shapes for; overrides a `@capo` directive.
+    pub capo: Option<usize>,
+    /// With a capo, print the concert chord next to each shape, e.g. `E (G)`.
+    pub show_concert_chords: bool,
+    /// Draw segno and coda markers as music symbols rather than words. The
+    /// symbols need a font that has them, which browsers usually do.
+    pub music_symbols: bool,
+    /// Print `#Chorus*` style references with all their lines instead of a
+    /// one-line pointer back to the original section.
+    pub full_references: bool,
+    /// Number the bars at the start of each row of measures.
+    pub measure_numbers: bool,
+    /// Letter the sections A, B, C in their headers. A section's own `@mark`
+    /// is printed either way.
+    pub rehearsal_marks: bool,
+}
 
 pub trait LeadSheetRenderer {
-    fn render_song(&self, engine:&dyn MarkupEngine,song: &Song) -> String;
+    fn render_song(&self, engine:&dyn MarkupEngine,song: &Song) -> String {
+        self.render_song_with_options(engine, song, &RenderOptions::default())
+    }
 
+    fn render_song_with_options(&self, engine: &dyn MarkupEngine, song: &Song, options: &RenderOptions) -> String;
 }
 
 pub struct DefaultLeadSheetRenderer;
 
 impl LeadSheetRenderer for DefaultLeadSheetRenderer {
-    fn render_song(&self, engine: &dyn MarkupEngine, song: &Song) -> String {
-        let mut output = String::new();
+    fn render_song_with_options(&self, engine: &dyn MarkupEngine, song: &Song, options: &RenderOptions) -> String {
+        let capo = capo_fret(song, options);
+        let shapes = (capo > 0).then(|| transpose::transpose_song(song.clone(), -(capo as isize)).0);
+        let chart = shapes.as_ref().unwrap_or(song);
+
+        let mut output = render_song_header(engine, song, chart, capo);
+        let marks = chart.rehearsal_marks();
+        let numbers = if options.measure_numbers { chart.numbered_measures() } else { Vec::new() };
+        for section in sections(song, chart, &marks, &numbers, capo, options) {
+            render_section(engine, &mut output, &section, options);
+        }
+        output
+    }
+}
+
+/// One section as it is drawn, with what it needs from the rest of the song.
+pub(crate) struct Section<'a> {
+    pub index: usize,
+    /// The section as printed: the chord shapes when there is a capo.
+    pub block: &'a Block,
+    /// The section at concert pitch.
+    pub song_block: &'a Block,
+    /// Set when each shape is printed next to its concert chord.
+    pub concert_block: Option<&'a Block>,
+    pub mark: Option<&'a str>,
+    pub numbers: &'a [NumberedMeasure<'a>],
+    pub capo: usize,
+}
 
-        if let Some(title) = song.directives.get("title") {
-            output.push_str(&engine.header(1, title));
-            output.push_str(&engine.linebreak());
+impl Section<'_> {
+    /// The header text, e.g. `[A] Verse 1`.
+    pub fn title(&self) -> String {
+        match self.mark {
+            Some(mark) => format!("[{}] {}", mark, self.block.name()),
+            None => self.block.name().to_string(),
         }
+    }
+
+    /// The number of the bar at `segment` of `line`, if it has one.
+    pub fn number(&self, line: usize, segment: usize) -> Option<u32> {
+        self.numbers
+            .iter()
+            .find(|m| m.block == self.index && m.line == line && m.segment == segment)
+            .map(|m| m.number)
+    }
+
+    /// The first numbered bar on `line` as its segment and number, so a row
+    /// that opens with a pickup is numbered from the bar after it.
+    pub fn first_number(&self, line: usize) -> Option<(usize, u32)> {
+        self.numbers
+            .iter()
+            .filter(|m| m.block == self.index && m.line == line)
+            .min_by_key(|m| m.segment)
+            .map(|m| (m.segment, m.number))
+    }
+
+    /// Whether the section is drawn as a one-line pointer to the original.
+    pub fn is_collapsed(&self, options: &RenderOptions) -> bool {
+        self.block.is_reference && !options.full_references
+    }
+}
+
+pub(crate) fn sections<'a>(
+    song: &'a Song,
+    chart: &'a Song,
+    marks: &'a [String],
+    numbers: &'a [NumberedMeasure<'a>],
+    capo: usize,
+    options: &RenderOptions,
+) -> Vec<Section<'a>> {
+    let concert = (capo > 0 && options.show_concert_chords).then_some(song);
+    chart
+        .blocks
+        .iter()
+        .enumerate()
+        .map(|(index, block)| Section {
+            index,
+            block,
+            song_block: song.blocks.get(index).unwrap_or(block),
+            concert_block: concert.and_then(|c| c.blocks.get(index)),
+            mark: if options.rehearsal_marks { marks.get(index).map(String::as_str) } else { block.mark() },
+            numbers,
+            capo,
+        })
+        .collect()
+}
+
+/// The title, artists and details list at the top of a chart.
+pub(crate) fn render_song_header(engine: &dyn MarkupEngine, song: &Song, chart: &Song, capo: usize) -> String {
+    let mut output = String::new();
+
+    if let Some(title) = song.directives.get("title") {
+        output.push_str(&engine.header(1, title));
+        output.push_str(&engine.linebreak());
+    }
+
+    let artists: Vec<&str> = song.directives.get_all("artist").collect();
+    if !artists.is_empty() {
+        output.push_str(&engine.italic(&artists.join(", ")));
+        output.push_str(&engine.linebreak());
+    }
+
+    let details = render_details(song, capo, chart.directives.get("key").map(String::as_str));
+    if !details.is_empty() {
+        let items: Vec<&str> = details.iter().map(String::as_str).collect();
+        output.push_str(&engine.list(false, &items));
+        output.push_str(&engine.linebreak());
+    }
+    output
+}
 
-        if let Some(artist) = song.directives.get("artist") {
-            output.push_str(&engine.italic(artist));
-            output.push_str(&engine.linebreak());
+/// Draws a section under its header with the chords over the lyrics.
+pub(crate) fn render_section(engine: &dyn MarkupEngine, output: &mut String, section: &Section, options: &RenderOptions) {
+    let block = section.block;
+    output.push_str(&engine.header(3, &section.title()));
+    output.push_str(&engine.linebreak());
+
+    if section.is_collapsed(options) {
+        let mut bars = section.numbers.iter().filter(|m| m.block == section.index).map(|m| m.number);
+        let mut repeat = format!("Repeat {}", block.name());
+        if let Some(first) = bars.next() {
+            match bars.next_back() {
+                Some(last) => write!(&mut repeat, " (bars {}–{})", first, last).unwrap(),
+                None => write!(&mut repeat, " (bar {})", first).unwrap(),
+            }
         }
+        output.push_str(&engine.italic(&repeat));
+        output.push_str(&engine.linebreak());
+        push_changes(engine, output, &render_changes(section, block.lines.len()));
+        return;
+    }
 
-        for block in &song.blocks {
-            let header_text = block.section_name.trim_start_matches('#');
-            output.push_str(&engine.header(3, header_text));
-            output.push_str(&engine.linebreak());
+    let mut pre_block_text = String::new();
+
+    for (line_index, line) in block.lines.iter().enumerate() {
+        let changes = render_changes(section, line_index);
+        if !changes.is_empty() {
+            flush_pre_block(engine, output, &mut pre_block_text);
+            push_changes(engine, output, &changes);
+        }
+        let concert_line = section.concert_block.and_then(|b| b.lines.get(line_index));
+        let number = section.first_number(line_index);
+        // A bar after a pickup carries its number inside it, as in the grid.
+        let inner_number = number.filter(|(segment, _)| *segment > 0);
+        let mut pairs = render_chord_lyric_lines(line, concert_line, inner_number, options);
+        if let (Some((0, number)), Some((chord_line, lyric_line))) = (number, pairs.first_mut()) {
+            let margin = format!("{} ", number);
+            chord_line.insert_str(0, &margin);
+            if !lyric_line.is_empty() {
+                lyric_line.insert_str(0, &" ".repeat(margin.len()));
+            }
+        }
 
-            let mut pre_block_text = String::new();
+        for (chord_line, lyric_line) in pairs {
+            if chord_line.trim().is_empty() && lyric_line.trim().is_empty() {
+                continue;
+            }
 
-            for line in &block.lines {
-                let pairs = render_chord_lyric_lines(line);
+            pre_block_text.push_str(&engine.bold(&chord_line));
+            pre_block_text.push('\n');
+            pre_block_text.push_str(&lyric_line);
+            pre_block_text.push('\n');
+        }
+    }
 
-                for (chord_line, lyric_line) in pairs {
-                    if chord_line.trim().is_empty() && lyric_line.trim().is_empty() {
-                        continue;
-                    }
+    flush_pre_block(engine, output, &mut pre_block_text);
+    push_changes(engine, output, &render_changes(section, block.lines.len()));
+}
 
-                    pre_block_text.push_str(&engine.bold(&chord_line));
-                    pre_block_text.push('\n');
-                    pre_block_text.push_str(&lyric_line);
-                    pre_block_text.push('\n');
-                }
+/// Writes out the chord and lyric rows gathered so far as one preformatted block.
+fn flush_pre_block(engine: &dyn MarkupEngine, output: &mut String, pre_block_text: &mut String) {
+    if !pre_block_text.trim().is_empty() {
+        output.push_str(&engine.pre_block(pre_block_text.trim_end_matches('\n')));
+        output.push_str(&engine.linebreak());
+    }
+    pre_block_text.clear();
+}
+
+pub(crate) fn push_changes(engine: &dyn MarkupEngine, output: &mut String, changes: &[String]) {
+    for change in changes {
+        output.push_str(&engine.italic(change));
+        output.push_str(&engine.linebreak());
+    }
+}
+
+/// The directives written before `line` of a section, e.g. `Key: D Major`.
+/// With a capo, a key change also names its shape key.
+pub(crate) fn render_changes(section: &Section, line: usize) -> Vec<String> {
+    section.song_block.directives_at(line)
+        .zip(section.block.directives_at(line))
+        // The rehearsal mark goes in the section header instead.
+        .filter(|(concert, _)| concert.name != "mark")
+        .map(|(concert, shape)| {
+            let mut change = format!("{}: {}", display_name(&concert.name), concert.value);
+            if concert.name == "key" && section.capo > 0 {
+                write!(&mut change, " ({} shapes)", shape.value).unwrap();
             }
+            change
+        })
+        .collect()
+}
 
-            if !pre_block_text.trim().is_empty() {
-                let pre_block_text = pre_block_text.trim_end_matches('\n');
-                output.push_str(&engine.pre_block(pre_block_text));
-                output.push_str(&engine.linebreak());
+/// One line per remaining directive, in source order, with repeated
+/// directives such as `@composer` joined onto a single line. The capo is
+/// folded into the key line and `@form` is drawn as a road map.
+fn render_details(song: &Song, capo: usize, shape_key: Option<&str>) -> Vec<String> {
+    let mut details = Vec::new();
+    let mut listed: Vec<&str> = Vec::new();
+    for directive in song.directives.iter() {
+        let name = directive.name.as_str();
+        if matches!(name, "title" | "artist" | "capo") || listed.contains(&name) {
+            continue;
+        }
+        listed.push(name);
+        let values: Vec<&str> = song.directives.get_all(name).collect();
+        let mut detail = format!("{}: {}", display_name(name), values.join(", "));
+        if name == "form"
+            && let Some(form) = song.directives.form() {
+            let steps: Vec<String> = form.entries.iter().map(|entry| entry.to_string()).collect();
+            detail = format!("Form: {}", steps.join(" → "));
+        }
+        if name == "key" && capo > 0 {
+            write!(&mut detail, ", Capo {}", capo).unwrap();
+            if let Some(shape_key) = shape_key {
+                write!(&mut detail, " ({} shapes)", shape_key).unwrap();
             }
         }
+        details.push(detail);
+    }
+    if capo > 0 && !song.directives.contains_key("key") {
+        details.push(format!("Capo {}", capo));
+    }
+    details
+}
 
-        output
+fn display_name(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
     }
 }
 
+pub(crate) fn capo_fret(song: &Song, options: &RenderOptions) -> usize {
+    options
+        .capo
+        .or_else(|| song.directives.capo())
+        .unwrap_or(0)
+}
+
+fn line_chords(line: &LyricLine) -> impl Iterator<Item = &Chord> {
+    line.segments
+        .iter()
+        .flat_map(|segment| segment.items().iter())
+        .filter_map(|item| match item {
+            ChordOrText::Chord(c) => Some(c),
+            _ => None,
+        })
+}
+
 
 
-fn render_chord_lyric_lines(line: &LyricLine) -> Vec<(String, String)> {
+fn render_chord_lyric_lines(
+    line: &LyricLine,
+    concert: Option<&LyricLine>,
+    number: Option<(usize, u32)>,
+    options: &RenderOptions,
+) -> Vec<(String, String)> {
     let mut result: Vec<(String, String)> = Vec::new();
+    let mut concert_chords = concert.into_iter().flat_map(line_chords);
 
     let mut chord_line = String::new();
     let mut lyric_line = String::new();
     let mut has_lyric = false; // <- new
 
-    for segment in &line.segments {
-        match segment {
-            Segment::Measure(items) | Segment::Inline(items) => {
-                for item in items {
-                    match item {
-                        ChordOrText::Chord(c) => {
-                            let chord = format_chord(c.clone());
-                            let width = chord.len().max(4); // same as before
-
-                            write!(&mut chord_line, "{:width$}", chord, width = width).unwrap();
-
-                            // 👇 only pad lyric line *after* lyrics have started
-                            if has_lyric {
-                                lyric_line.push_str(&" ".repeat(width));
-                            }
+    for (index, segment) in line.segments.iter().enumerate() {
+        if let Segment::Measure(measure) = segment {
+            let mut opening = measure.start.symbol().to_string();
+            if let Some(volta) = &measure.volta {
+                opening.push_str(&volta.label());
+            }
+            opening.push(' ');
+            if let Some((_, number)) = number.filter(|(segment, _)| *segment == index) {
+                write!(&mut opening, "{} ", number).unwrap();
+            }
+            push_bar(&mut chord_line, &mut lyric_line, has_lyric, &opening);
+        }
+
+        for item in segment.items() {
+            match item {
+                ChordOrText::Chord(c) => {
+                    let mut chord = c.to_string();
+                    if let Some(concert_chord) = concert_chords.next() {
+                        write!(&mut chord, " ({})", concert_chord).unwrap();
+                    }
+                    push_chord(&mut chord_line, &mut lyric_line, has_lyric, &chord);
+                }
+
+                ChordOrText::NoChord(_) | ChordOrText::RepeatMeasure(_) | ChordOrText::Slash(_) | ChordOrText::Rest(_) => {
+                    push_symbol(&mut chord_line, &mut lyric_line, has_lyric, symbol_text(item, options));
+                }
+
+                ChordOrText::Marker(..) => {
+                    push_bar(&mut chord_line, &mut lyric_line, has_lyric, &format!("{} ", symbol_text(item, options)));
+                    // Before the words start, line them up after the marker
+                    // so the chord that follows sits over its syllable.
+                    if !has_lyric && matches!(segment, Segment::Inline(..)) {
+                        lyric_line.push_str(&" ".repeat(chord_line.width().saturating_sub(lyric_line.width())));
+                    }
+                }
+
+                ChordOrText::Text(text, _) => {
+                    // Handle embedded newlines like before
+                    let parts: Vec<&str> = text.split('\n').collect();
+
+                    for (i, part) in parts.iter().enumerate() {
+                        if i > 0 {
+                            // flush current visual line
+                            result.push((chord_line.clone(), lyric_line.clone()));
+                            chord_line.clear();
+                            lyric_line.clear();
+                            has_lyric = false; // reset for the new line
                         }
 
-                        ChordOrText::Text(text) => {
-                            // Handle embedded newlines like before
-                            let parts: Vec<&str> = text.split('\n').collect();
-
-                            for (i, part) in parts.iter().enumerate() {
-                                if i > 0 {
-                                    // flush current visual line
-                                    result.push((chord_line.clone(), lyric_line.clone()));
-                                    chord_line.clear();
-                                    lyric_line.clear();
-                                    has_lyric = false; // reset for the new line
-                                }
-
-                                // respect any spaces the user put in `part` explicitly
-                                let len = part.len();
-                                chord_line.push_str(&" ".repeat(len));
-                                lyric_line.push_str(part);
-                                if !part.is_empty() {
-                                    has_lyric = true;
-                                }
-                            }
+                        // respect any spaces the user put in `part` explicitly;
+                        // wide characters such as 日本 take two columns each
+                        chord_line.push_str(&" ".repeat(part.width()));
+                        lyric_line.push_str(part);
+                        if !part.is_empty() {
+                            has_lyric = true;
                         }
                     }
                 }
             }
         }
+
+        if let Segment::Measure(measure) = segment {
+            let next_start = match line.segments.get(index + 1) {
+                Some(Segment::Measure(next)) => Some(next.start),
+                _ => None,
+            };
+            // A barline shared with the next measure is drawn as its opening.
+            if let Some(end) = measure.end
+                && (next_start != Some(end) || measure.repeat_count.is_some()) {
+                let mut closing = end.symbol().to_string();
+                if let Some(count) = measure.repeat_count {
+                    write!(&mut closing, " x{}", count).unwrap();
+                }
+                if next_start.is_some() {
+                    closing.push(' ');
+                }
+                push_bar(&mut chord_line, &mut lyric_line, has_lyric, &closing);
+            }
+        }
     }
 
     if !chord_line.is_empty() || !lyric_line.is_empty() {
@@ -121,33 +402,47 @@ fn render_chord_lyric_lines(line: &LyricLine) -> Vec<(String, String)> {
 
 
 
-fn format_chord(chord: Chord) -> String {
-    let mut s = format!("{:?}", chord.root.letter);
-
-    match chord.root.accidental {
-        Accidental::Sharp => s.push('#'),
-        Accidental::Flat => s.push('b'),
-        Accidental::None => {}
+/// How `N.C.`, `%`, `/`, rests and road-map markers are printed; empty for
+/// chords and lyrics.
+pub(crate) fn symbol_text(item: &ChordOrText, options: &RenderOptions) -> &'static str {
+    match item {
+        ChordOrText::NoChord(_) => "N.C.",
+        ChordOrText::RepeatMeasure(_) => "%",
+        ChordOrText::Slash(_) => "/",
+        ChordOrText::Rest(_) if options.music_symbols => "\u{1D13D}",
+        ChordOrText::Rest(_) => "rest",
+        ChordOrText::Marker(marker, _) if options.music_symbols => marker.symbol(),
+        ChordOrText::Marker(marker, _) => marker.name(),
+        ChordOrText::Chord(_) | ChordOrText::Text(..) => "",
     }
+}
 
-    if let Some(q) = &chord.quality {
-        s.push_str(q);
-    }
+/// Writes a chord on the chord row in a column at least four wide.
+fn push_chord(chord_line: &mut String, lyric_line: &mut String, has_lyric: bool, chord: &str) {
+    let width = chord.width().max(4);
+    chord_line.push_str(chord);
+    chord_line.push_str(&" ".repeat(width - chord.width()));
 
-    for ext in &chord.extensions {
-        if let Some(e) = ext {
-            s.push_str(e);
-        }
+    // only pad the lyric line *after* lyrics have started
+    if has_lyric {
+        lyric_line.push_str(&" ".repeat(width));
     }
+}
 
-    if let Some(bass) = &chord.bass {
-        s.push('/');
-        s.push_str(&format!("{:?}", bass.letter));
-        match bass.accidental {
-            Accidental::Sharp => s.push('#'),
-            Accidental::Flat => s.push('b'),
-            Accidental::None => {}
-        }
+/// Writes a chart symbol like `N.C.` or `%` in a chord column, spaced off from
+/// its neighbours so a symbol that fills the column doesn't run into them.
+fn push_symbol(chord_line: &mut String, lyric_line: &mut String, has_lyric: bool, symbol: &str) {
+    let gap = if chord_line.is_empty() || chord_line.ends_with(' ') { "" } else { " " };
+    let symbol = format!("{}{} ", gap, symbol);
+    push_chord(chord_line, lyric_line, has_lyric, &symbol);
+}
+
+/// Writes barline or marker text on the chord row, keeping any lyrics under it aligned.
+fn push_bar(chord_line: &mut String, lyric_line: &mut String, has_lyric: bool, bar: &str) {
+    let gap = if chord_line.is_empty() || chord_line.ends_with(' ') { "" } else { " " };
+    let bar = format!("{}{}", gap, bar);
+    chord_line.push_str(&bar);
+    if has_lyric {
+        lyric_line.push_str(&" ".repeat(bar.width()));
     }
-    s
 }
@@ -0,0 +1,100 @@
+use std::fmt::Write;
+
+use crate::diagnostics::*;
+use crate::parser::Rule;
+
+pub use crate::diagnostics::error_to_diagnostic;
+
+/// Formats a diagnostic rustc-style, quoting the offending source line and
+/// underlining the span:
+///
+/// ```text
+/// error[E0002]: expected a chord root A–G after '['
+///  --> song.lsml:3:2
+///   |
+/// 3 | [Z] a
+///   |  ^
+/// ```
+pub fn render_report(source: &str, file_name: &str, diagnostic: &Diagnostic) -> String {
+    let mut output = format!("{}\n", diagnostic);
+    let Some(span) = diagnostic.span else {
+        return output;
+    };
+
+    let (line, column) = span.line_col(source);
+    let line_text = source.lines().nth(line - 1).unwrap_or("").trim_end_matches('\r');
+    let gutter = " ".repeat(line.to_string().len());
+    let line_start = source[..span.start.min(source.len())].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line_end = line_start + line_text.len();
+    let underline_end = span.end.clamp(span.start, line_end);
+    let width = source
+        .get(span.start..underline_end)
+        .map(|s| s.chars().count())
+        .unwrap_or(0)
+        .max(1);
+
+    writeln!(output, "{}--> {}:{}:{}", gutter, file_name, line, column).unwrap();
+    writeln!(output, "{} |", gutter).unwrap();
+    writeln!(output, "{} | {}", line, line_text).unwrap();
+    writeln!(output, "{} | {}{}", gutter, " ".repeat(column - 1), "^".repeat(width)).unwrap();
+    output
+}
+
+impl DescribeRule for Rule {
+    fn description(&self) -> &'static str {
+        match self {
+            Rule::note | Rule::key => "a chord root A–G",
+            Rule::accidental | Rule::sign | Rule::literal_natural => "an accidental '#' or 'b'",
+            Rule::chord | Rule::chord_elements | Rule::chord_token => "a chord in brackets like '[C]'",
+            Rule::quality => "a chord quality like 'm' or 'maj'",
+            Rule::extension | Rule::major_seventh | Rule::tone => "a chord extension like '7', 'b9' or 'add9'",
+            Rule::tensions => "tensions in parentheses like '(b9, #11)'",
+            Rule::slash | Rule::slash_chord => "a slash bass like '/G'",
+            Rule::chart_symbol | Rule::bare_symbol => "'N.C.', '%', '/' or a rest",
+            Rule::duration => "a length in beats like ':2'",
+            Rule::beat_dots => "beat dots like '..'",
+            Rule::no_chord => "'N.C.'",
+            Rule::simile => "'%' to repeat the measure",
+            Rule::beat_slash => "'/' for another beat",
+            Rule::rest => "a rest like '[rest]'",
+            Rule::directive | Rule::directive_list | Rule::directive_start => "a directive like '@title: ...'",
+            Rule::name => "a directive name",
+            Rule::value => "a directive value",
+            Rule::section_header | Rule::section_header_start | Rule::section_title => "a section header like '#Verse'",
+            Rule::section_reference => "'*' to reuse an earlier section",
+            Rule::blocks | Rule::block => "a section starting with '#'",
+            Rule::measure | Rule::measures => "a measure like '| [C] [G] |'",
+            Rule::bar | Rule::shared_bar => "a barline like '|', '||', '|:' or ':|'",
+            Rule::volta => "an ending like '1.'",
+            Rule::repeat_count => "a repeat count like 'x3'",
+            Rule::marker_token | Rule::marker => "a navigation marker like '{Segno}' or '{D.S. al Coda}'",
+            Rule::pickup_token | Rule::pickup => "a pickup marker '{Pickup}'",
+            Rule::lyric_line | Rule::lyric_block | Rule::chord_or_text | Rule::text_token
+            | Rule::text_word | Rule::text_space => "lyrics or a chord",
+            Rule::song => "a directive like '@title: ...' or a section starting with '#'",
+            Rule::EOI => "the end of the file",
+            Rule::WHITESPACE => "whitespace",
+            Rule::COMMENT | Rule::comment_start => "a comment",
+        }
+    }
+
+    fn code(&self) -> Option<&'static str> {
+        match self {
+            Rule::note | Rule::key | Rule::accidental | Rule::sign | Rule::literal_natural
+            | Rule::chord | Rule::chord_elements | Rule::chord_token | Rule::quality
+            | Rule::extension | Rule::major_seventh | Rule::tone | Rule::tensions
+            | Rule::slash | Rule::slash_chord | Rule::chart_symbol | Rule::no_chord
+            | Rule::simile | Rule::beat_slash | Rule::rest | Rule::duration => Some(codes::INVALID_CHORD),
+            Rule::directive | Rule::directive_list | Rule::directive_start | Rule::name | Rule::value => Some(codes::INVALID_DIRECTIVE),
+            Rule::section_header | Rule::section_header_start | Rule::section_title
+            | Rule::section_reference => Some(codes::INVALID_SECTION),
+            _ => None,
+        }
+    }
+
+    // Comments may go almost anywhere, and a bad chord is far more likely
+    // than a misspelt `N.C.`, so listing either only adds noise.
+    fn is_noise(&self) -> bool {
+        matches!(self, Rule::COMMENT | Rule::chart_symbol | Rule::bare_symbol)
+    }
+}
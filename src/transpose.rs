@@ -1,185 +1,351 @@
 use crate::ast::*;
+use crate::diagnostics::*;
+use crate::metadata::{Key, MetadataError};
+use crate::parser::Rule;
 
-const CHROMATIC_SCALE:[&str; 12] = [
-"C", "Db", "D", "Eb", "E", "F",
-"Gb", "G", "Ab", "A", "Bb", "B"
+const LETTERS: [NoteLetter; 7] = [
+    NoteLetter::C, NoteLetter::D, NoteLetter::E, NoteLetter::F,
+    NoteLetter::G, NoteLetter::A, NoteLetter::B
 ];
 
-fn relative_transpose(chord: Chord, semitones: isize) -> Chord {
-    if semitones == 0 {
-        chord
-    } else if semitones < 0 {
-        let steps = 12 - (semitones.abs() as usize % 12);
-        let transposed_chord = transpose_chord(chord, steps);
-        transposed_chord
-    } else {
-        let steps = (semitones % 12) as usize;
-        let transposed_chord =transpose_chord(chord, steps);
-        transposed_chord
+const MAJOR_SCALE_STEPS: [usize; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// Which accidentals to use when spelling transposed chords.
+///
+/// `Auto` follows the destination key; `Sharps` and `Flats` pick the matching
+/// enharmonic key where one exists (F# vs Gb, C# vs Db, B vs Cb) and spell
+/// every chromatic chord with that accidental.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Spelling {
+    #[default]
+    Auto,
+    Sharps,
+    Flats,
+}
+
+/// Pitch class of the major scale `key` is a mode of.
+fn relative_major(key: &Key) -> usize {
+    (pitch_class(&key.tonic) + 12 - MAJOR_SCALE_STEPS[key.mode.degree()]) % 12
+}
+
+/// The major scale `key` is a mode of, spelled so the tonic keeps its letter.
+fn parent_scale(key: &Key) -> [Note; 7] {
+    let offset = key.mode.degree();
+    let tonic_index = letter_index(&key.tonic.letter);
+    let major_letter = LETTERS[(tonic_index + 7 - offset) % 7].clone();
+    let major_tonic = spell_letter(major_letter, relative_major(key))
+        .unwrap_or_else(|| key.tonic.clone());
+    major_scale(&major_tonic)
+}
+
+/// Parses a transposition target; a bare tonic such as `"Bb"` keeps the
+/// mode of `source`.
+fn parse_target(target: &str, source: &Key) -> Result<Key, MetadataError> {
+    match target.trim().parse::<Note>() {
+        Ok(tonic) => Ok(Key { tonic, mode: source.mode }),
+        Err(_) => target.parse(),
     }
 }
 
-fn transpose_chord(chord: Chord, steps: usize) -> Chord {
-    let new_root = relative_note(chord.root.clone(), steps);
-        let new_bass = if chord.bass.is_none() {
-            None
-        } else {
-            Some(relative_note(chord.bass.clone().unwrap(), steps))
-        };
-    let new_extensions = chord.extensions.clone();
-    let new_quality = chord.quality.clone();
-    Chord {
-        root: new_root,
-        inversion: chord.inversion,
-        quality: new_quality,
-        extensions: new_extensions,
-        bass: new_bass,
+/// How notes are named in the destination key: diatonic notes come from the
+/// key's scale, everything else uses `chromatic` accidentals.
+struct KeySpelling {
+    scale: Option<[Note; 7]>,
+    chromatic: Accidental,
+}
+
+impl KeySpelling {
+    fn spell(&self, pitch: usize) -> Note {
+        if let Some(scale) = &self.scale
+            && let Some(note) = scale.iter().find(|n| pitch_class(n) == pitch) {
+            return note.clone();
+        }
+        chromatic_note(pitch, &self.chromatic)
     }
 }
 
-fn relative_note(chord_note: Note, steps: usize) -> Note {
-    let original_key = note_to_string(chord_note.letter, chord_note.accidental);
-    let from_index = CHROMATIC_SCALE.iter().position(|x| x == &find_enharmonic_spelling(&original_key)).expect(format!("Expected {} key to be in the chromatic scale", original_key).as_str());
-    let new_index = (from_index + steps) % 12;
-    let transposed_key = CHROMATIC_SCALE[new_index];
-    string_to_note(transposed_key)
+fn letter_index(letter: &NoteLetter) -> usize {
+    LETTERS.iter().position(|l| l == letter).unwrap_or(0)
 }
-fn note_to_string(letter: NoteLetter, accidental: Accidental) -> String {
 
-    let mut output = match letter {
-        NoteLetter::A => "A".to_string(),
-        NoteLetter::B => "B".to_string(),
-        NoteLetter::C => "C".to_string(),
-        NoteLetter::D => "D".to_string(),
-        NoteLetter::E => "E".to_string(),
-        NoteLetter::F => "F".to_string(),
-        NoteLetter::G => "G".to_string(),
-    };
+fn letter_pitch_class(letter: &NoteLetter) -> usize {
+    MAJOR_SCALE_STEPS[letter_index(letter)]
+}
 
-    match accidental {
-        Accidental::Sharp => output.push_str("#"),
-        Accidental::Flat => output.push_str("b"),
-        Accidental::None => output.push_str(""),
+fn pitch_class(note: &Note) -> usize {
+    let natural = letter_pitch_class(&note.letter);
+    match note.accidental {
+        Accidental::Sharp => (natural + 1) % 12,
+        Accidental::Flat => (natural + 11) % 12,
+        Accidental::None => natural,
     }
-    output
 }
 
-fn string_to_note(key: &str) -> Note {
-    let mut note = Note{
-        letter: NoteLetter::A,
-        accidental: Accidental::None
+/// Spells `pitch` on the given letter, if it is at most one accidental away.
+fn spell_letter(letter: NoteLetter, pitch: usize) -> Option<Note> {
+    let accidental = match (pitch + 12 - letter_pitch_class(&letter)) % 12 {
+        0 => Accidental::None,
+        1 => Accidental::Sharp,
+        11 => Accidental::Flat,
+        _ => return None,
     };
-    match key.chars().next().unwrap() {
-        'A' => note.letter = NoteLetter::A,
-        'B' => note.letter = NoteLetter::B,
-        'C' => note.letter = NoteLetter::C,
-        'D' => note.letter = NoteLetter::D,
-        'E' => note.letter = NoteLetter::E,
-        'F' => note.letter = NoteLetter::F,
-        'G' => note.letter = NoteLetter::G,
-        _ => { }
+    Some(Note { letter, accidental })
+}
+
+fn chromatic_note(pitch: usize, accidental: &Accidental) -> Note {
+    let natural = LETTERS.iter().find(|l| letter_pitch_class(l) == pitch);
+    if let Some(letter) = natural {
+        return Note { letter: letter.clone(), accidental: Accidental::None };
     }
+    match accidental {
+        Accidental::Sharp => Note { letter: chromatic_letter(pitch + 11), accidental: Accidental::Sharp },
+        _ => Note { letter: chromatic_letter(pitch + 1), accidental: Accidental::Flat },
+    }
+}
 
-    if key.len() > 1 {
-        match key.chars().nth(1).unwrap() {
-            '#' => note.accidental = Accidental::Sharp,
-            'b' => note.accidental = Accidental::Flat,
-            _ => {}
-        }
+fn chromatic_letter(pitch: usize) -> NoteLetter {
+    LETTERS
+        .iter()
+        .find(|l| letter_pitch_class(l) == pitch % 12)
+        .cloned()
+        .unwrap_or(NoteLetter::C)
+}
+
+fn major_scale(tonic: &Note) -> [Note; 7] {
+    let tonic_pitch = pitch_class(tonic);
+    let tonic_index = letter_index(&tonic.letter);
+    std::array::from_fn(|degree| {
+        let letter = LETTERS[(tonic_index + degree) % 7].clone();
+        let pitch = (tonic_pitch + MAJOR_SCALE_STEPS[degree]) % 12;
+        spell_letter(letter, pitch).unwrap_or_else(|| chromatic_note(pitch, &Accidental::Flat))
+    })
+}
+
+/// The accidental a major key is written with; C major has none.
+fn key_accidental(major_tonic: &Note) -> Accidental {
+    match (&major_tonic.letter, &major_tonic.accidental) {
+        (NoteLetter::C, Accidental::None) => Accidental::None,
+        (NoteLetter::F, Accidental::None) | (_, Accidental::Flat) => Accidental::Flat,
+        _ => Accidental::Sharp,
     }
-    note
-}
-
-fn find_enharmonic_spelling(key: &str) -> String {
-   match key {
-       "Cb" => "B",
-       "C#" => "Db",
-       "D#" => "Eb",
-       "E#" => "F",
-       "Fb" => "E",
-       "F#" => "Gb",
-       "G#" => "Ab",
-       "A#" => "Bb",
-       "B#" => "C",
-       _ => key
-   }
-   .to_string()
-}
-
-fn relative_key(key: &str, semitones: isize) -> String {
-    let parts: Vec<&str> = key.split_whitespace().collect();
-    if parts.len() != 2 {
-        return key.to_string(); // fallback
+}
+
+/// Picks the tonic of the destination major key for a pitch class.
+///
+/// Only keys with at most seven accidentals are considered. Under `Auto`,
+/// F#/Gb stays in flats when the source key uses flats or the shortest route
+/// there goes down, and uses sharps otherwise.
+fn major_tonic(pitch: usize, spelling: Spelling, source: &Accidental, steps: usize) -> Note {
+    let sharp = chromatic_note(pitch, &Accidental::Sharp);
+    let flat = chromatic_note(pitch, &Accidental::Flat);
+    let black_key = sharp.accidental == Accidental::Sharp;
+    let prefer_flats = match spelling {
+        Spelling::Sharps => black_key && !matches!(pitch, 1 | 6),
+        Spelling::Flats => true,
+        Spelling::Auto => match pitch {
+            6 => *source == Accidental::Flat || steps > 6,
+            _ => black_key,
+        },
+    };
+    match (pitch, prefer_flats) {
+        (11, true) => Note { letter: NoteLetter::C, accidental: Accidental::Flat },
+        (_, true) => flat,
+        (_, false) => sharp,
     }
+}
 
-    let key_name = find_enharmonic_spelling(parts[0]);
-    let key_mode = parts[1];
-    let from_index = CHROMATIC_SCALE.iter().position(|&x| x == key_name).unwrap_or(0);
+fn transpose_key(key: &Key, steps: usize, spelling: Spelling) -> Key {
+    let source = key_accidental(&parent_scale(key)[0]);
+    let major_pitch = (relative_major(key) + steps) % 12;
+    let major = major_scale(&major_tonic(major_pitch, spelling, &source, steps));
+    Key {
+        tonic: major[key.mode.degree()].clone(),
+        mode: key.mode,
+    }
+}
 
-    let steps = if semitones < 0 {
-        12 - ((-semitones) as usize % 12)
-    } else {
-        semitones as usize % 12
+fn key_spelling(key: Option<&Key>, spelling: Spelling) -> KeySpelling {
+    let scale = key.map(parent_scale);
+    let chromatic = match spelling {
+        Spelling::Sharps => Accidental::Sharp,
+        Spelling::Flats => Accidental::Flat,
+        Spelling::Auto => match &scale {
+            Some(scale) => key_accidental(&scale[0]),
+            None => Accidental::Flat,
+        },
     };
+    KeySpelling { scale, chromatic }
+}
 
-    let new_index = (from_index + steps) % 12;
-    let new_key = CHROMATIC_SCALE[new_index].to_string();
+fn normalize_steps(semitones: isize) -> usize {
+    semitones.rem_euclid(12) as usize
+}
+
+fn transpose_chord(chord: Chord, steps: usize, spelling: &KeySpelling) -> Chord {
+    let new_root = relative_note(chord.root, steps, spelling);
+    let new_bass = chord.bass.map(|bass| relative_note(bass, steps, spelling));
+    Chord {
+        root: new_root,
+        bass: new_bass,
+        ..chord
+    }
+}
+
+fn relative_note(chord_note: Note, steps: usize, spelling: &KeySpelling) -> Note {
+    spelling.spell((pitch_class(&chord_note) + steps) % 12)
+}
 
-    format!("{} {}", new_key, key_mode)
+fn transpose_elements(elements: Vec<ChordOrText>, steps: usize, spelling: &KeySpelling) -> Vec<ChordOrText> {
+    elements
+        .into_iter()
+        .map(|e| match e {
+            ChordOrText::Chord(c) => ChordOrText::Chord(transpose_chord(c, steps, spelling)),
+            other => other,
+        })
+        .collect()
 }
 
-pub fn transpose_song(song: Song, semitones: isize) -> Song {
+/// Transposes the `@key` changes written before `line`; chords after them are
+/// spelled from the last one.
+fn transpose_key_changes(directives: &mut [InlineDirective], line: usize, steps: usize, spelling: Spelling, current: &mut KeySpelling) {
+    for change in directives.iter_mut().filter(|d| d.line == line && d.directive.name == "key") {
+        if let Ok(key) = change.directive.value.parse::<Key>() {
+            let target = transpose_key(&key, steps, spelling);
+            *current = key_spelling(Some(&target), spelling);
+            change.directive.value = target.to_string();
+        }
+    }
+}
+
+fn transpose_blocks(blocks: Vec<Block>, steps: usize, spelling: Spelling, mut key_spelling: KeySpelling) -> Vec<Block> {
     let mut transposed_blocks = Vec::new();
 
-    for block in song.blocks {
+    for block in blocks {
         let mut new_lines = Vec::new();
+        let mut directives = block.directives;
 
         for line in block.lines {
+            transpose_key_changes(&mut directives, new_lines.len(), steps, spelling, &mut key_spelling);
             let mut new_segments = Vec::new();
 
             for segment in line.segments {
                 match segment {
-                    Segment::Measure(elements) => {
-                        let new_elements = elements
-                            .into_iter()
-                            .map(|e| match e {
-                                ChordOrText::Chord(c) => ChordOrText::Chord(relative_transpose(c, semitones)),
-                                ChordOrText::Text(t) => ChordOrText::Text(t),
-                            })
-                            .collect();
-
-                        new_segments.push(Segment::Measure(new_elements));
+                    Segment::Measure(measure) => {
+                        new_segments.push(Segment::Measure(Measure {
+                            items: transpose_elements(measure.items, steps, &key_spelling),
+                            ..measure
+                        }));
                     }
-                    Segment::Inline(elements) => {
-                        let new_elements = elements
-                            .into_iter()
-                            .map(|e| match e {
-                                ChordOrText::Chord(c) => ChordOrText::Chord(relative_transpose(c, semitones)),
-                                ChordOrText::Text(t) => ChordOrText::Text(t),
-                            })
-                            .collect();
-
-                        new_segments.push(Segment::Inline(new_elements));
+                    Segment::Inline(elements, span) => {
+                        new_segments.push(Segment::Inline(transpose_elements(elements, steps, &key_spelling), span));
                     }
                 }
             }
 
             new_lines.push(LyricLine {
                 segments: new_segments,
+                pickup: line.pickup,
+                span: line.span,
             });
         }
+        transpose_key_changes(&mut directives, new_lines.len(), steps, spelling, &mut key_spelling);
 
         transposed_blocks.push(Block {
             section_name: block.section_name,
+            section: block.section,
             lines: new_lines,
+            directives,
+            is_reference: block.is_reference,
+            span: block.span,
         });
     }
 
+    transposed_blocks
+}
+
+/// Transposes every chord in the song by `semitones`, spelling the result
+/// from the destination key. Key changes inside sections move with the song,
+/// and the chords after each one are spelled from its new key.
+///
+/// A song without a usable `@key` still has its chords transposed; the
+/// returned warnings explain that the key could not be followed.
+pub fn transpose_song(song: Song, semitones: isize) -> (Song, Vec<Diagnostic>) {
+    transpose_song_with_spelling(song, semitones, Spelling::Auto)
+}
+
+/// Like [`transpose_song`], but lets the caller force sharps or flats.
+pub fn transpose_song_with_spelling(song: Song, semitones: isize, spelling: Spelling) -> (Song, Vec<Diagnostic>) {
+    let steps = normalize_steps(semitones);
+    let mut diagnostics = Vec::new();
     let mut new_directives = song.directives.clone();
-    new_directives.insert("key".to_string(), relative_key(song.directives.get("key").unwrap(), semitones));
 
-    Song {
+    let source_key = match song.directives.get("key") {
+        Some(key) => match key.parse::<Key>() {
+            Ok(parsed) => Some(parsed),
+            Err(error) => {
+                diagnostics.push(Diagnostic::warning(
+                    format!("Unrecognised @key directive \"{}\" ({}); it was left unchanged", key, error),
+                    codes::UNRECOGNISED_KEY,
+                    None));
+                None
+            }
+        },
+        None => {
+            diagnostics.push(Diagnostic::warning(
+                "Song has no @key directive; chords were transposed without key-aware spelling",
+                codes::MISSING_KEY,
+                None));
+            None
+        }
+    };
+    let target_key = source_key.as_ref().map(|k| transpose_key(k, steps, spelling));
+    let key_spelling = key_spelling(target_key.as_ref(), spelling);
+    if let Some(target_key) = &target_key {
+        new_directives.insert("key".to_string(), target_key.to_string());
+    }
+
+    let song = Song {
         directives: new_directives,
-        blocks: transposed_blocks,
+        blocks: transpose_blocks(song.blocks, steps, spelling, key_spelling),
+        comments: song.comments,
+        span: song.span,
+    };
+    (song, diagnostics)
+}
+
+/// Transposes the song into `target`, e.g. `"Eb Major"` or just `"Eb"` to
+/// keep the song's mode. The interval is measured from the song's `@key`.
+pub fn transpose_to_key(song: Song, target: &str) -> ParseResult<Song, Rule> {
+    transpose_to_key_with_spelling(song, target, Spelling::Auto)
+}
+
+/// Like [`transpose_to_key`], but lets the caller force sharps or flats.
+pub fn transpose_to_key_with_spelling(song: Song, target: &str, spelling: Spelling) -> ParseResult<Song, Rule> {
+    let source = song
+        .directives
+        .get("key")
+        .ok_or_else(|| transpose_error(format!("Cannot transpose to {}: the song has no @key directive", target)))?;
+    let source_key: Key = source
+        .parse()
+        .map_err(|e| transpose_error(format!("Unrecognised @key directive: {}", e)))?;
+    let target_key = parse_target(target, &source_key)
+        .map_err(|e| transpose_error(format!("Unrecognised target key: {}", e)))?;
+    if !target_key.mode.is_equivalent(source_key.mode) {
+        return Err(transpose_error(format!(
+            "Cannot transpose from {} to {}: the modes differ",
+            source, target
+        )));
     }
-}
\ No newline at end of file
+
+    let steps = (pitch_class(&target_key.tonic) + 12 - pitch_class(&source_key.tonic)) % 12;
+    let key_spelling = key_spelling(Some(&target_key), spelling);
+    let mut new_directives = song.directives.clone();
+    new_directives.insert("key".to_string(), target_key.to_string());
+
+    Ok(Song {
+        directives: new_directives,
+        blocks: transpose_blocks(song.blocks, steps, spelling, key_spelling),
+        comments: song.comments,
+        span: song.span,
+    })
+}
@@ -3,6 +3,7 @@ use pest::Parser;
 use leadsheetml::parser::*;
 use leadsheetml::ast::*;
 use leadsheetml::render::*;
+use leadsheetml::grid::*;
 use leadsheetml::transpose::*;
 use markup_engine::{HtmlEngine, MarkdownEngine};
 
@@ -179,7 +180,7 @@ fn test_parses_simple_line_to_ast(){
     let input = "[C]Hello, [G]world!";
     let parsed = LeadSheetMLParser::parse(Rule::lyric_line, input);
     assert!(parsed.is_ok());
-    parse_line(parsed.unwrap().next().unwrap()).unwrap();
+    parse_line(parsed.unwrap().next().unwrap(), &mut Warnings::default()).unwrap();
 }
 
 #[test]
@@ -187,7 +188,7 @@ fn test_parses_simple_measure_to_ast(){
     let input = "| [C]Hello, [G]world! |";
     let parsed = LeadSheetMLParser::parse(Rule::measure, input);
     assert!(parsed.is_ok());
-    parse_measure(parsed.unwrap().next().unwrap()).unwrap();
+    parse_measure(parsed.unwrap().next().unwrap(), &mut Warnings::default()).unwrap();
 }
 
 #[test]
@@ -195,16 +196,18 @@ fn test_parses_simple_chord_to_ast(){
     let input = "[C]";
     let parsed = LeadSheetMLParser::parse(Rule::chord_token, input);
     assert!(parsed.is_ok());
-    let parsed = parse_chord_token(parsed.unwrap().next().unwrap());
+    let parsed = parse_chord_token(parsed.unwrap().next().unwrap(), &mut Warnings::default());
     assert_eq!(parsed.unwrap(), Chord {
         root: Note {
             letter: NoteLetter::C,
             accidental: Accidental::None
         },
-        inversion: None,
-        quality: None,
-        extensions: Vec::new(),
-        bass: None
+        quality: Quality::Major,
+        extension: None,
+        alterations: Vec::new(),
+        bass: None,
+        duration: None,
+        span: Default::default()
     })
 }
 
@@ -216,30 +219,34 @@ fn test_parse_complex_chords_to_ast(){
     let parsed2 = LeadSheetMLParser::parse(Rule::chord_token, input2);
     assert!(parsed.is_ok());
     assert!(parsed2.is_ok());
-    let parsed_chord = parse_chord_token(parsed.unwrap().next().unwrap());
-    let parsed_chord2 = parse_chord_token(parsed2.unwrap().next().unwrap());
+    let parsed_chord = parse_chord_token(parsed.unwrap().next().unwrap(), &mut Warnings::default());
+    let parsed_chord2 = parse_chord_token(parsed2.unwrap().next().unwrap(), &mut Warnings::default());
     assert_eq!(parsed_chord.unwrap(), Chord {
         root: Note {
         letter: NoteLetter::C,
         accidental: Accidental::None
         },
-        inversion: None,
-        quality: None,
-        extensions:Vec::new(),
+        quality: Quality::Major,
+        extension: None,
+        alterations: Vec::new(),
         bass: Some(Note
         { letter: NoteLetter::G,
             accidental: Accidental::None
         }
-        )});
+        ),
+        duration: None,
+        span: Default::default()});
     assert_eq!(parsed_chord2.unwrap(), Chord{
         root: Note {
             letter: NoteLetter::C,
             accidental: Accidental::Sharp
         },
-        inversion: None,
-        quality: Some("maj".to_string()),
-        extensions: vec![Some("7".to_string()),Some("b5".to_string())],
-        bass: None
+        quality: Quality::Major,
+        extension: Some(Extension { degree: 7, major_seventh: true }),
+        alterations: vec![Alteration::Flat(5)],
+        bass: None,
+        duration: None,
+        span: Default::default()
     });
 }
 
@@ -296,7 +303,7 @@ fn test_parses_simple_section_header_to_ast(){
 fn test_parses_simple_block_to_ast(){
     let input = "#Intro\n| Hello, world! |";
     let parsed = LeadSheetMLParser::parse(Rule::block, input);
-    parse_block(parsed.unwrap().next().unwrap()).unwrap();
+    parse_block(parsed.unwrap().next().unwrap(), &mut Warnings::default()).unwrap();
 }
 
 #[test]
@@ -304,7 +311,7 @@ fn test_parses_complex_block_to_ast(){
     let input = "#Verse\n[D] Sunday at [D/C#] six when they [D/C] close both the gates\n[D] A [Em] wi [D] dowed [Em]pair\n[D]Still [Em]sit[D]ting [A7]there,\n[G]Wonder [Em]if they're [A]late for [D]church\nAnd its [D/C#]cold, so they [D/C]fasten their coats\n[D]And [Em]cross [D]the [Em]grass, [D]theyre [Em]al[D]ways [A7]last.";
     let parsed = LeadSheetMLParser::parse(Rule::block, input);
     assert!(parsed.is_ok());
-    parse_block(parsed.unwrap().next().unwrap()).unwrap();
+    parse_block(parsed.unwrap().next().unwrap(), &mut Warnings::default()).unwrap();
 }
 
 #[test]
@@ -322,7 +329,7 @@ fn test_parse_simple_song_to_ast(){
         panic!();
     }
     assert!(parsed.is_ok());
-    parse_song(parsed.unwrap().next().unwrap()).unwrap();
+    parse_song(parsed.unwrap().next().unwrap(), &mut Warnings::default()).unwrap();
 }
 
 #[test]
@@ -334,7 +341,7 @@ fn test_parse_song_to_ast(){
         panic!();
     }
     assert!(parsed.is_ok());
-    parse_song(parsed.unwrap().next().unwrap()).unwrap();
+    parse_song(parsed.unwrap().next().unwrap(), &mut Warnings::default()).unwrap();
 }
 
 #[test]
@@ -346,7 +353,7 @@ fn test_parse_simple_song_to_ml(){
         panic!();
     }
     assert!(parsed.is_ok());
-    let song = parse_song(parsed.unwrap().next().unwrap()).unwrap();
+    let song = parse_song(parsed.unwrap().next().unwrap(), &mut Warnings::default()).unwrap();
     let engine = MarkdownEngine;
     let md = DefaultLeadSheetRenderer.render_song(&engine, &song);
     println!("{}", md)
@@ -361,7 +368,7 @@ fn test_parse_song_to_ml(){
         panic!();
     }
     assert!(parsed.is_ok());
-    let song = parse_song(parsed.unwrap().next().unwrap()).unwrap();
+    let song = parse_song(parsed.unwrap().next().unwrap(), &mut Warnings::default()).unwrap();
     let engine = MarkdownEngine;
     let md = DefaultLeadSheetRenderer.render_song(&engine, &song);
     println!("{}", md)
@@ -376,7 +383,7 @@ fn test_parse_simple_song_to_html(){
         panic!();
     }
     assert!(parsed.is_ok());
-    let song = parse_song(parsed.unwrap().next().unwrap()).unwrap();
+    let song = parse_song(parsed.unwrap().next().unwrap(), &mut Warnings::default()).unwrap();
     let engine = HtmlEngine;
     let md = DefaultLeadSheetRenderer.render_song(&engine, &song);
     println!("{}", md)
@@ -391,7 +398,7 @@ fn test_parse_song_to_html(){
         panic!();
     }
     assert!(parsed.is_ok());
-    let song = parse_song(parsed.unwrap().next().unwrap()).unwrap();
+    let song = parse_song(parsed.unwrap().next().unwrap(), &mut Warnings::default()).unwrap();
     let engine = HtmlEngine;
     let md = DefaultLeadSheetRenderer.render_song(&engine, &song);
     println!("{}", md)
@@ -415,7 +422,7 @@ fn test_transpose_dflat_major_up_5_semitones() {
     [Db] Hello [Gb] World
     ").unwrap();
 
-    let transposed = transpose_song(song, 5);
+    let (transposed, _) = transpose_song(song, 5);
     let key = transposed.directives.get("key").unwrap();
     assert_eq!(key, "Gb Major");
     let engine = MarkdownEngine;
@@ -433,7 +440,7 @@ fn test_transpose_csharp_minor_down_13_semitones() {
     [C#m] Hello [F#m] World
     ").unwrap();
 
-    let transposed = transpose_song(song, -13);
+    let (transposed, _) = transpose_song(song, -13);
     let key = transposed.directives.get("key").unwrap();
     assert_eq!(key, "C Minor");
     let engine = MarkdownEngine;
@@ -471,7 +478,7 @@ fn transposes_around_circle_of_fifths_and_back() {
     ];
 
     for (i, expected) in expected_keys.iter().enumerate() {
-        song = transpose_song(song, 7); // +7 semitones = perfect fifth
+        song = transpose_song(song, 7).0; // +7 semitones = perfect fifth
 
         let key = song
             .directives
@@ -494,4 +501,1160 @@ fn test_invalid_chord_errors() {
     let result = parse_song_from_str(input);
 
     assert!(result.is_err());
-}
\ No newline at end of file
+}
+fn song_chords(song: &Song) -> Vec<Chord> {
+    let mut chords = Vec::new();
+    for block in &song.blocks {
+        for line in &block.lines {
+            for segment in &line.segments {
+                for item in segment.items() {
+                    if let ChordOrText::Chord(c) = item {
+                        chords.push(c.clone());
+                    }
+                }
+            }
+        }
+    }
+    chords
+}
+
+fn note(letter: NoteLetter, accidental: Accidental) -> Note {
+    Note { letter, accidental }
+}
+
+#[test]
+fn test_transpose_sharp_key_spells_with_sharps() {
+    let song = parse_song_from_str("
+    @key: E Major
+
+    #Verse
+    [E] Hello [F#7] World [C#m/G#] again
+    ").unwrap();
+
+    let (transposed, _) = transpose_song(song, 2);
+    assert_eq!(transposed.directives.get("key").unwrap(), "F# Major");
+    let chords = song_chords(&transposed);
+    assert_eq!(chords[0].root, note(NoteLetter::F, Accidental::Sharp));
+    assert_eq!(chords[1].root, note(NoteLetter::G, Accidental::Sharp));
+    assert_eq!(chords[2].root, note(NoteLetter::D, Accidental::Sharp));
+    assert_eq!(chords[2].bass, Some(note(NoteLetter::A, Accidental::Sharp)));
+}
+
+#[test]
+fn test_transpose_flat_key_spells_with_flats() {
+    let song = parse_song_from_str("
+    @key: C Major
+
+    #Verse
+    [C] Hello [D7] World [G/B] again
+    ").unwrap();
+
+    let (transposed, _) = transpose_song(song, 3);
+    assert_eq!(transposed.directives.get("key").unwrap(), "Eb Major");
+    let chords = song_chords(&transposed);
+    assert_eq!(chords[0].root, note(NoteLetter::E, Accidental::Flat));
+    assert_eq!(chords[1].root, note(NoteLetter::F, Accidental::None));
+    assert_eq!(chords[2].root, note(NoteLetter::B, Accidental::Flat));
+    assert_eq!(chords[2].bass, Some(note(NoteLetter::D, Accidental::None)));
+}
+
+#[test]
+fn test_transpose_minor_and_modal_keys() {
+    let song = parse_song_from_str("
+    @key: A Minor
+
+    #Verse
+    [Am] Hello [E7] World
+    ").unwrap();
+    let (transposed, _) = transpose_song(song, 6);
+    assert_eq!(transposed.directives.get("key").unwrap(), "D# Minor");
+    assert_eq!(song_chords(&transposed)[1].root, note(NoteLetter::A, Accidental::Sharp));
+
+    let song = parse_song_from_str("
+    @key: D Dorian
+
+    #Verse
+    [Dm7] Hello [G7] World
+    ").unwrap();
+    let (transposed, _) = transpose_song(song, 1);
+    assert_eq!(transposed.directives.get("key").unwrap(), "Eb Dorian");
+    assert_eq!(song_chords(&transposed)[1].root, note(NoteLetter::A, Accidental::Flat));
+}
+
+#[test]
+fn test_transpose_spelling_override() {
+    let source = "
+    @key: E Major
+
+    #Verse
+    [E] Hello [F#7] World
+    ";
+
+    let (flats, _) = transpose_song_with_spelling(parse_song_from_str(source).unwrap(), 2, Spelling::Flats);
+    assert_eq!(flats.directives.get("key").unwrap(), "Gb Major");
+    assert_eq!(song_chords(&flats)[1].root, note(NoteLetter::A, Accidental::Flat));
+
+    let (sharps, _) = transpose_song_with_spelling(parse_song_from_str(source).unwrap(), -3, Spelling::Sharps);
+    assert_eq!(sharps.directives.get("key").unwrap(), "C# Major");
+    assert_eq!(song_chords(&sharps)[1].root, note(NoteLetter::D, Accidental::Sharp));
+
+    let (respelled, _) = transpose_song_with_spelling(parse_song_from_str(source).unwrap(), 0, Spelling::Flats);
+    assert_eq!(respelled.directives.get("key").unwrap(), "E Major");
+    assert_eq!(song_chords(&respelled)[1].root, note(NoteLetter::F, Accidental::Sharp));
+}
+
+#[test]
+fn test_transpose_to_named_key() {
+    let source = "
+    @key: G Minor
+
+    #Verse
+    [Gm7] Hello [D7] World [Ebmaj7/G] again
+    ";
+
+    let transposed = transpose_to_key(parse_song_from_str(source).unwrap(), "E Minor").unwrap();
+    assert_eq!(transposed.directives.get("key").unwrap(), "E Minor");
+    let chords = song_chords(&transposed);
+    assert_eq!(chords[0].root, note(NoteLetter::E, Accidental::None));
+    assert_eq!(chords[1].root, note(NoteLetter::B, Accidental::None));
+    assert_eq!(chords[2].root, note(NoteLetter::C, Accidental::None));
+    assert_eq!(chords[2].bass, Some(note(NoteLetter::E, Accidental::None)));
+
+    let bare = transpose_to_key(parse_song_from_str(source).unwrap(), "Bb").unwrap();
+    assert_eq!(bare.directives.get("key").unwrap(), "Bb Minor");
+    assert_eq!(song_chords(&bare)[1].root, note(NoteLetter::F, Accidental::None));
+}
+
+#[test]
+fn test_transpose_to_enharmonic_key_keeps_requested_spelling() {
+    let source = "
+    @key: E Major
+
+    #Verse
+    [E] Hello [B7] World
+    ";
+    let transposed = transpose_to_key(parse_song_from_str(source).unwrap(), "Gb Major").unwrap();
+    assert_eq!(transposed.directives.get("key").unwrap(), "Gb Major");
+    assert_eq!(song_chords(&transposed)[1].root, note(NoteLetter::D, Accidental::Flat));
+
+    // Notes outside the key follow a forced spelling.
+    let source = "@key: E Major\n#Verse\n[E] Hello [D] World";
+    let auto = transpose_to_key(parse_song_from_str(source).unwrap(), "F Major").unwrap();
+    assert_eq!(song_chords(&auto)[1].root, note(NoteLetter::E, Accidental::Flat));
+    let sharps = transpose_to_key_with_spelling(parse_song_from_str(source).unwrap(), "F Major", Spelling::Sharps).unwrap();
+    assert_eq!(sharps.directives.get("key").unwrap(), "F Major");
+    assert_eq!(song_chords(&sharps)[1].root, note(NoteLetter::D, Accidental::Sharp));
+}
+
+#[test]
+fn test_transpose_to_key_errors() {
+    let keyless = parse_song_from_str("@title: Test\n#Verse\n[C] Hello").unwrap();
+    assert!(transpose_to_key(keyless, "D Major").is_err());
+
+    let song = parse_song_from_str("@key: A Minor\n#Verse\n[Am] Hello").unwrap();
+    assert!(transpose_to_key(song, "C Major").is_err());
+
+    let song = parse_song_from_str("@key: A Minor\n#Verse\n[Am] Hello").unwrap();
+    assert!(transpose_to_key(song, "H Minor").is_err());
+}
+
+#[test]
+fn test_capo_renders_shapes_with_concert_key() {
+    let song = parse_song_from_str("
+    @title: Test
+    @key: A Major
+    @capo: 2
+
+    #Verse
+    [A] Hello [E7] World
+    ").unwrap();
+
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &song);
+    assert!(md.contains("Key: A Major, Capo 2 (G Major shapes)"));
+    assert!(md.contains("**G "));
+    assert!(md.contains("D7"));
+    assert!(!md.contains("E7"));
+
+    let options = RenderOptions { capo: Some(2), show_concert_chords: true, ..RenderOptions::default() };
+    let html = DefaultLeadSheetRenderer.render_song_with_options(&HtmlEngine, &song, &options);
+    assert!(html.contains("G (A)"));
+    assert!(html.contains("D7 (E7)"));
+
+    let options = RenderOptions { capo: Some(0), show_concert_chords: true, ..RenderOptions::default() };
+    let concert = DefaultLeadSheetRenderer.render_song_with_options(&MarkdownEngine, &song, &options);
+    assert!(concert.contains("Key: A Major\n"));
+    assert!(concert.contains("E7"));
+
+    // Shapes don't need a key to work from.
+    let song = parse_song_from_str("@capo: 2\n#Verse\n[A] Hello [E7] World").unwrap();
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &song);
+    assert!(md.contains("Capo 2\n"));
+    assert!(md.contains("D7"));
+}
+
+#[test]
+fn test_transpose_without_key_warns() {
+    let song = parse_song_from_str("@title: Test\n#Verse\n[C] Hello [G7] World").unwrap();
+    let (transposed, warnings) = transpose_song(song, 2);
+    assert!(!transposed.directives.contains_key("key"));
+    assert_eq!(song_chords(&transposed)[1].root, note(NoteLetter::A, Accidental::None));
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].severity, leadsheetml::diagnostics::DiagnosticSeverity::Warning);
+
+    let song = parse_song_from_str("@key: Cmaj\n#Verse\n[C] Hello").unwrap();
+    let (transposed, warnings) = transpose_song(song, 2);
+    assert_eq!(transposed.directives.get("key").unwrap(), "Cmaj");
+    assert_eq!(warnings.len(), 1);
+}
+
+#[test]
+fn test_error_report_points_at_source() {
+    let input = "@title: Test\n#Verse\n[C] fine\n[Z] broken";
+    let error = parse_song_from_str(input).unwrap_err();
+    let diagnostic = leadsheetml::report::error_to_diagnostic(&error, input);
+    assert_eq!(diagnostic.code.as_deref(), Some("E0002"));
+    assert_eq!(diagnostic.span.unwrap().line_col(input), (4, 2));
+
+    let report = leadsheetml::report::render_report(input, "song.lsml", &diagnostic);
+    assert_eq!(report, "error[E0002]: expected a chord root A–G after '['\n --> song.lsml:4:2\n  |\n4 | [Z] broken\n  |  ^\n");
+}
+
+#[test]
+fn test_source_span_line_col_counts_characters() {
+    let input = "#Canción\n[C]corazón [G]mío";
+    let span = leadsheetml::diagnostics::SourceSpan { start: input.find("[G]").unwrap(), end: input.len() };
+    assert_eq!(span.line_col(input), (2, 12));
+}
+
+#[test]
+fn test_parse_with_diagnostics_collects_warnings() {
+    let input = "@title: One\n@title: Two\n#Intro\n#Verse\n[C8] Hello [G] World\n#Solo\n| [C] [G]";
+    let (song, diagnostics) = parse_song_with_diagnostics(input).unwrap();
+    assert_eq!(song.blocks.len(), 3);
+    let codes: Vec<&str> = diagnostics.iter().filter_map(|d| d.code.as_deref()).collect();
+    assert_eq!(codes, vec!["W003", "W004", "W006", "W005"]);
+    assert!(diagnostics.iter().all(|d| d.severity == leadsheetml::diagnostics::DiagnosticSeverity::Warning));
+    assert_eq!(diagnostics[2].span.unwrap().line_col(input), (5, 3));
+}
+
+#[test]
+fn test_parse_song_from_str_rejects_what_diagnostics_warns_about() {
+    assert!(parse_song_from_str("#Intro\n#Verse\n[C] Hello").is_err());
+    assert!(parse_song_from_str("#Solo\n| [C] [G]").is_err());
+    assert!(parse_song_from_str("#Solo\n| [C] [G] |\n#Solo*").is_ok());
+    assert!(parse_song_from_str("#A\n[C] Hi\n@capo: 2").is_err());
+    assert!(parse_song_from_str("#A\n[C] Hi\n@key: G").is_ok());
+    assert!(parse_song_with_diagnostics("#Solo\n| [C] [G]").is_ok());
+}
+
+#[test]
+fn test_parse_with_diagnostics_clean_song() {
+    let input = "@title: Test\n@key: C Major\n#Verse\n[Cmaj7] Hello [G7] World\n#Solo\n| [C] [G] || [F] [C] |";
+    let (_, diagnostics) = parse_song_with_diagnostics(input).unwrap();
+    assert!(diagnostics.is_empty());
+}
+
+#[test]
+fn test_recovering_parser_skips_bad_lines() {
+    let input = "@title: Test\n#Verse\n[C] Hello\n[Z] broken\n[G] World\n#Chorus\n[F] Sing [Cq] along\n[C] again";
+    let (song, diagnostics) = parse_song_recovering(input);
+    assert_eq!(song.blocks.len(), 2);
+    assert_eq!(song.directives.get("title").unwrap(), "Test");
+    let roots: Vec<NoteLetter> = song_chords(&song).into_iter().map(|c| c.root.letter).collect();
+    assert_eq!(roots, vec![NoteLetter::C, NoteLetter::G, NoteLetter::C]);
+
+    let errors: Vec<(usize, usize)> = diagnostics
+        .iter()
+        .filter(|d| d.severity == leadsheetml::diagnostics::DiagnosticSeverity::Error)
+        .map(|d| d.span.unwrap().line_col(input))
+        .collect();
+    assert_eq!(errors, vec![(4, 2), (7, 12)]);
+}
+
+#[test]
+fn test_recovering_parser_unfinished_chord() {
+    let input = "#Verse\n[C] Hello [\n[G] World";
+    let (song, diagnostics) = parse_song_recovering(input);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].span.unwrap().line_col(input), (2, 11));
+    let roots: Vec<NoteLetter> = song_chords(&song).into_iter().map(|c| c.root.letter).collect();
+    assert_eq!(roots, vec![NoteLetter::G]);
+}
+
+#[test]
+fn test_recovering_parser_skips_section_with_bad_header() {
+    let input = "#Verse\n[C] Hello\n#\n[D] lost\n#Chorus\n[F] Sing [Cq] along\n[G] again";
+    let (song, diagnostics) = parse_song_recovering(input);
+    let names: Vec<&str> = song.blocks.iter().map(|b| b.section_name.as_str()).collect();
+    assert_eq!(names, vec!["Verse", "Chorus"]);
+    assert_eq!(song.blocks[0].lines.len(), 1);
+    let roots: Vec<NoteLetter> = song_chords(&song).into_iter().map(|c| c.root.letter).collect();
+    assert_eq!(roots, vec![NoteLetter::C, NoteLetter::G]);
+    let errors: Vec<(usize, usize)> = diagnostics.iter().map(|d| d.span.unwrap().line_col(input)).collect();
+    assert_eq!(errors, vec![(3, 2), (6, 12)]);
+}
+
+#[test]
+fn test_recovering_parser_skips_zero_duration() {
+    let input = "#Verse\n[C] Hello\n[C:0] broken\n[G] World";
+    let (song, diagnostics) = parse_song_recovering(input);
+    let roots: Vec<NoteLetter> = song_chords(&song).into_iter().map(|c| c.root.letter).collect();
+    assert_eq!(roots, vec![NoteLetter::C, NoteLetter::G]);
+    let errors: Vec<(usize, usize)> = diagnostics.iter().map(|d| d.span.unwrap().line_col(input)).collect();
+    assert_eq!(errors, vec![(3, 3)]);
+}
+
+#[test]
+fn test_recovering_parser_skips_leading_beat_dots() {
+    let input = "#Solo\n| [C] [G] |\n| .. [C] |\n| [F] |";
+    let (song, diagnostics) = parse_song_recovering(input);
+    let roots: Vec<NoteLetter> = song_chords(&song).into_iter().map(|c| c.root.letter).collect();
+    assert_eq!(roots, vec![NoteLetter::C, NoteLetter::G, NoteLetter::F]);
+    let errors: Vec<(usize, usize)> = diagnostics.iter().map(|d| d.span.unwrap().line_col(input)).collect();
+    assert_eq!(errors, vec![(3, 3)]);
+}
+
+#[test]
+fn test_recovering_parser_skips_unknown_reference() {
+    let input = "#Verse\n[C] Hello\n#Bridge*\n#Chorus\n[F] Sing";
+    let (song, diagnostics) = parse_song_recovering(input);
+    let names: Vec<&str> = song.blocks.iter().map(|b| b.section_name.as_str()).collect();
+    assert_eq!(names, vec!["Verse", "Chorus"]);
+    let errors: Vec<(usize, usize)> = diagnostics.iter().map(|d| d.span.unwrap().line_col(input)).collect();
+    assert_eq!(errors, vec![(3, 1)]);
+}
+
+#[test]
+fn test_recovering_parser_clean_song_matches_strict_parser() {
+    let input = "@title: Test\n#Verse\n[C] Hello [G] World";
+    let (song, diagnostics) = parse_song_recovering(input);
+    assert!(diagnostics.is_empty());
+    assert_eq!(song_chords(&song), song_chords(&parse_song_from_str(input).unwrap()));
+}
+
+#[cfg(feature = "spans")]
+#[test]
+fn test_ast_nodes_carry_source_spans() {
+    let input = "@title: Test\n#Verse\n[C] Hello [G7/B] World\n#Solo\n| [F] [C] |";
+    let song = parse_song_from_str(input).unwrap();
+    let text = |span: leadsheetml::diagnostics::SourceSpan| &input[span.start..span.end];
+
+    assert_eq!(text(song.span), input);
+    assert_eq!(text(song.blocks[1].span).trim(), "#Solo\n| [F] [C] |");
+    assert_eq!(text(song.blocks[0].lines[0].span).trim(), "[C] Hello [G7/B] World");
+
+    let Segment::Inline(items, _) = &song.blocks[0].lines[0].segments[0] else { panic!("expected inline segment") };
+    assert_eq!(text(items[0].span()), "C");
+    assert_eq!(text(items[1].span()), "Hello ");
+    assert_eq!(text(items[2].span()), "G7/B");
+    assert_eq!(items[2].span().line_col(input), (3, 12));
+
+    let measure = &song.blocks[1].lines[0].segments[0];
+    assert_eq!(text(measure.span()), "| [F] [C] |");
+
+    let (transposed, _) = transpose_song(song, 2);
+    assert_eq!(text(song_chords(&transposed)[1].span), "G7/B");
+}
+
+#[test]
+fn test_directives_keep_order_and_repeats() {
+    let input = "@title: Autumn Leaves\n@composer: Joseph Kosma\n@lyricist: Johnny Mercer\n@composer: Jacques Prevert\n@key: G Minor\n#Verse\n[Gm] Hello";
+    let (song, diagnostics) = parse_song_with_diagnostics(input).unwrap();
+    assert!(diagnostics.is_empty());
+    assert_eq!(song.directives.len(), 5);
+    let composers: Vec<&str> = song.directives.get_all("composer").collect();
+    assert_eq!(composers, vec!["Joseph Kosma", "Jacques Prevert"]);
+    let names: Vec<&str> = song.directives.iter().map(|d| d.name.as_str()).collect();
+    assert_eq!(names, vec!["title", "composer", "lyricist", "composer", "key"]);
+
+    let (transposed, _) = transpose_song(song, 2);
+    let names: Vec<&str> = transposed.directives.iter().map(|d| d.name.as_str()).collect();
+    assert_eq!(names, vec!["title", "composer", "lyricist", "composer", "key"]);
+    assert_eq!(transposed.directives.get("key").unwrap(), "A Minor");
+
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &transposed);
+    assert!(md.contains("- Composer: Joseph Kosma, Jacques Prevert\n- Lyricist: Johnny Mercer\n- Key: A Minor\n"));
+
+    // Only directives from the source say where they were written.
+    assert!(transposed.directives.iter().all(|d| d.span.is_some()));
+    let mut directives = transposed.directives.clone();
+    directives.insert("capo", "2");
+    assert!(directives.iter().find(|d| d.name == "capo").unwrap().span.is_none());
+}
+
+#[test]
+fn test_single_valued_directive_keeps_first_value() {
+    let input = "@title: One\n@title: Two\n#Verse\n[C] Hello";
+    let (song, diagnostics) = parse_song_with_diagnostics(input).unwrap();
+    assert_eq!(song.directives.get("title").unwrap(), "One");
+    assert_eq!(diagnostics.len(), 1);
+
+    let mut directives = song.directives.clone();
+    directives.insert("title", "Three");
+    let values: Vec<&str> = directives.get_all("title").collect();
+    assert_eq!(values, vec!["Three"]);
+}
+
+#[test]
+fn test_typed_metadata() {
+    use leadsheetml::metadata::*;
+
+    let input = "@title: Blue Bossa\n@key: C Minor\n@time: 4/4\n@tempo: Med Swing 160\n@capo: 2\n@arranger: Zoë\n#Head\n[Cm7] Hello";
+    let (song, diagnostics) = parse_song_with_diagnostics(input).unwrap();
+    assert!(diagnostics.is_empty());
+    assert_eq!(song.directives.key(), Some(Key { tonic: note(NoteLetter::C, Accidental::None), mode: Mode::Minor }));
+    assert_eq!(song.directives.time(), Some(TimeSignature { beats: 4, unit: 4 }));
+    assert_eq!(song.directives.tempo(), Some(Tempo { bpm: Some(160), text: Some("Med Swing".to_string()) }));
+    assert_eq!(song.directives.capo(), Some(2));
+    assert_eq!(song.directives.get("arranger").unwrap(), "Zoë");
+
+    for text in ["Eb Major", "F# Dorian", "A Aeolian", "Bb Minor"] {
+        assert_eq!(text.parse::<Key>().unwrap().to_string(), text);
+    }
+    assert_eq!("Em".parse::<Key>().unwrap().to_string(), "E Minor");
+    assert_eq!("G".parse::<Key>().unwrap().to_string(), "G Major");
+    for text in ["120 BPM", "Ballad", "Med Swing 160 BPM"] {
+        assert_eq!(text.parse::<Tempo>().unwrap().to_string(), text);
+    }
+    for text in ["Med Swing 160", "160 bpm Med Swing", "Ballad 3 72 BPM", "72 Ballad 3"] {
+        let tempo: Tempo = text.parse().unwrap();
+        assert_eq!(tempo.to_string().parse::<Tempo>().unwrap(), tempo);
+    }
+    assert_eq!("Med Swing 160".parse::<Tempo>().unwrap().to_string(), "Med Swing 160 BPM");
+    assert_eq!("Ballad 3 72 BPM".parse::<Tempo>().unwrap().bpm, Some(72));
+    assert_eq!("6/8".parse::<TimeSignature>().unwrap().to_string(), "6/8");
+}
+
+#[test]
+fn test_invalid_metadata_warns() {
+    let input = "@key: H Major\n@time: 4/3\n@tempo: 0\n@capo: two\n@mood: 4/3\n#Verse\n[C] Hello";
+    let (song, diagnostics) = parse_song_with_diagnostics(input).unwrap();
+    let codes: Vec<&str> = diagnostics.iter().filter_map(|d| d.code.as_deref()).collect();
+    assert_eq!(codes, vec!["W008", "W008", "W008", "W008"]);
+    assert_eq!(diagnostics[1].span.unwrap().line_col(input), (2, 1));
+    assert!(song.directives.key().is_none());
+    assert!(song.directives.time().is_none());
+    assert_eq!(song.directives.get("mood").unwrap(), "4/3");
+}
+
+#[test]
+fn test_directive_value_runs_to_end_of_line() {
+    let input = "@title: a1 (Live at Wembley)\n@subtitle: Don't Stop Me Now!\n@key: E\n#Verse\n[E] Hello";
+    let song = parse_song_from_str(input).unwrap();
+    assert_eq!(song.directives.get("title").unwrap(), "a1 (Live at Wembley)");
+    assert_eq!(song.directives.get("subtitle").unwrap(), "Don't Stop Me Now!");
+    assert_eq!(song.directives.get("key").unwrap(), "E");
+    assert_eq!(song.blocks[0].section_name, "Verse");
+
+    let (transposed, warnings) = transpose_song(song, 2);
+    assert!(warnings.is_empty());
+    assert_eq!(transposed.directives.get("key").unwrap(), "F# Major");
+}
+
+#[test]
+fn test_comments_are_discarded() {
+    let input = "@title: Test\n// arrangement: keep it sparse\n@key: C Major\n#Verse // first time only\n[C] Hello /* breathe */ [G] world\n| [F] [C] | // turnaround\n/* the bridge\n   is optional */\n#Bridge\n[Am] Later";
+    let song = parse_song_from_str(input).unwrap();
+    assert!(song.comments.is_empty());
+    assert_eq!(song.directives.len(), 2);
+    assert_eq!(song.blocks.len(), 2);
+    assert_eq!(song_chords(&song).len(), 5);
+
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &song);
+    assert!(!md.contains("arrangement"));
+    assert!(!md.contains("breathe"));
+    assert!(!md.contains("turnaround"));
+    assert!(!md.contains("optional"));
+    assert!(md.contains("world"));
+}
+
+#[test]
+fn test_comments_can_be_kept() {
+    let input = "@title: Test\n// sparse\n#Verse\n[C] Hello /* breathe */ world";
+    let options = ParseOptions { keep_comments: true };
+    let song = parse_song_with_options(input, &options).unwrap();
+    let comments: Vec<(CommentKind, &str)> = song.comments.iter().map(|c| (c.kind, c.text.as_str())).collect();
+    assert_eq!(comments, vec![(CommentKind::Line, "sparse"), (CommentKind::Block, "breathe")]);
+
+    assert!(parse_song_from_str("#Verse\n[C] Hello /* never closed").is_err());
+}
+
+#[test]
+fn test_double_slash_inside_words_is_lyrics() {
+    let input = "#Verse\n[C] see http://example.com and/or// [G] now // a note";
+    let song = parse_song_with_options(input, &ParseOptions { keep_comments: true }).unwrap();
+    let texts: Vec<&str> = song.blocks[0].lines[0].segments[0]
+        .items()
+        .iter()
+        .filter_map(|item| match item {
+            ChordOrText::Text(text, _) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(texts, vec!["see http://example.com and/or// ", "now "]);
+    assert_eq!(song.comments.len(), 1);
+    assert_eq!(song.comments[0].text, "a note");
+}
+
+fn song_measures(song: &Song) -> Vec<Measure> {
+    song.blocks
+        .iter()
+        .flat_map(|block| &block.lines)
+        .flat_map(|line| &line.segments)
+        .filter_map(|segment| match segment {
+            Segment::Measure(measure) => Some(measure.clone()),
+            Segment::Inline(..) => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_repeats_and_endings() {
+    let input = "#Head\n|: [C] [Am] | [F] [G] :| x3\n| [C] | [G] |1. [F] [C] :|2,3. [F] [G] |]";
+    let (song, diagnostics) = parse_song_with_diagnostics(input).unwrap();
+    assert!(diagnostics.is_empty());
+    let measures = song_measures(&song);
+    let bars: Vec<(Barline, Option<Barline>)> = measures.iter().map(|m| (m.start, m.end)).collect();
+    assert_eq!(bars, vec![
+        (Barline::StartRepeat, Some(Barline::Single)),
+        (Barline::Single, Some(Barline::EndRepeat)),
+        (Barline::Single, Some(Barline::Single)),
+        (Barline::Single, Some(Barline::Single)),
+        (Barline::Single, Some(Barline::EndRepeat)),
+        (Barline::EndRepeat, Some(Barline::Final)),
+    ]);
+    assert_eq!(measures[1].repeat_count, Some(3));
+    assert_eq!(measures[4].volta, Some(Volta { endings: vec![1] }));
+    assert_eq!(measures[5].volta, Some(Volta { endings: vec![2, 3] }));
+    assert_eq!(song.blocks[0].lines.len(), 2);
+    assert_eq!(song_chords(&song).len(), 10);
+}
+
+#[test]
+fn test_misplaced_repeat_count_warns() {
+    let (_, diagnostics) = parse_song_with_diagnostics("#Head\n| [C] [G] | x2").unwrap();
+    let codes: Vec<&str> = diagnostics.iter().filter_map(|d| d.code.as_deref()).collect();
+    assert_eq!(codes, vec!["W009"]);
+}
+
+#[test]
+fn test_render_repeats() {
+    let input = "#Head\n|: [C] | [G] :| x3\n|1. [F] :|2. [G] |]";
+    let song = parse_song_from_str(input).unwrap();
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &song);
+    assert!(md.contains("**|: C   | G   :| x3**"));
+    assert!(md.contains("**|1. F   :|2. G   |]**"));
+    let html = DefaultLeadSheetRenderer.render_song(&HtmlEngine, &song);
+    assert!(html.contains("<b>|: C   | G   :| x3</b>"));
+}
+
+#[test]
+fn test_parse_navigation_markers() {
+    let input = "#Head\n| {Segno} [C] | [F] {To Coda} [G] | [C] {d.s. al coda} |\n#Coda\n| {Coda} [C] {Fine} |]";
+    let (song, diagnostics) = parse_song_with_diagnostics(input).unwrap();
+    assert!(diagnostics.is_empty());
+    let markers: Vec<NavigationMarker> = song
+        .blocks
+        .iter()
+        .flat_map(|block| &block.lines)
+        .flat_map(|line| &line.segments)
+        .flat_map(|segment| segment.items())
+        .filter_map(|item| match item {
+            ChordOrText::Marker(marker, _) => Some(*marker),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(markers, vec![
+        NavigationMarker::Segno,
+        NavigationMarker::ToCoda,
+        NavigationMarker::DalSegno(JumpEnd::Coda),
+        NavigationMarker::Coda,
+        NavigationMarker::Fine,
+    ]);
+    assert_eq!(song_chords(&song).len(), 5);
+
+    assert!(parse_song_from_str("#Head\n| [C] {Sengo} |").is_err());
+}
+
+#[test]
+fn test_unmatched_navigation_warns() {
+    let input = "#Head\n| [C] {D.S. al Coda} |\n| [F] {D.C. al Fine} |";
+    let (_, diagnostics) = parse_song_with_diagnostics(input).unwrap();
+    let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(messages, vec![
+        "D.S. al Coda has no matching {Segno}",
+        "D.S. al Coda has no matching {Coda}",
+        "D.C. al Fine has no matching {Fine}",
+    ]);
+    assert!(diagnostics.iter().all(|d| d.code.as_deref() == Some("W010")));
+    let places: Vec<(usize, usize)> = diagnostics.iter().map(|d| d.span.unwrap().line_col(input)).collect();
+    assert_eq!(places, vec![(2, 7), (2, 7), (3, 7)]);
+
+    // A reference points at the markers it copies.
+    let input = "#Head\n| [C] {To Coda} |\n#Verse\n[G] Hi\n#Head*";
+    let (_, diagnostics) = parse_song_with_diagnostics(input).unwrap();
+    let places: Vec<(usize, usize)> = diagnostics.iter().map(|d| d.span.unwrap().line_col(input)).collect();
+    assert_eq!(places, vec![(2, 7), (2, 7)]);
+}
+
+#[test]
+fn test_render_navigation_markers() {
+    let song = parse_song_from_str("#Head\n| {Segno} [C] | [G] {D.S. al Coda} |\n#Coda\n| {Coda} [C] |]").unwrap();
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &song);
+    assert!(md.contains("**| Segno C   | G   D.S. al Coda |**"));
+    let options = RenderOptions { music_symbols: true, ..RenderOptions::default() };
+    let html = DefaultLeadSheetRenderer.render_song_with_options(&HtmlEngine, &song, &options);
+    assert!(html.contains("<b>| \u{1D10B} C   | G   D.S. al \u{1D10C} |</b>"));
+    assert!(html.contains("<b>| \u{1D10C} C   |]</b>"));
+
+    // A marker before the words keeps the chords after it over their syllables.
+    let song = parse_song_from_str("#Head\n{Segno} [D]a {To Coda} [G]b").unwrap();
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &song);
+    assert!(md.contains("**Segno D     To Coda G    **\n      a             b\n"), "{}", md);
+}
+
+#[test]
+fn test_parse_form_directive() {
+    use leadsheetml::metadata::*;
+
+    let form: Form = "Intro V1 C x2 Bridge C Outro".parse().unwrap();
+    let sections: Vec<(&str, u32)> = form.entries.iter().map(|e| (e.section.as_str(), e.times)).collect();
+    assert_eq!(sections, vec![("Intro", 1), ("V1", 1), ("C", 2), ("Bridge", 1), ("C", 1), ("Outro", 1)]);
+    assert_eq!(form.to_string(), "Intro V1 C x2 Bridge C Outro");
+
+    let form: Form = "Intro, Verse 1, Chorus x2".parse().unwrap();
+    assert_eq!(form.entries[1].section, "Verse 1");
+    assert_eq!(form.entries[2].times, 2);
+    assert_eq!(form.to_string(), "Intro, Verse 1, Chorus x2");
+
+    assert!("x2 Intro".parse::<Form>().is_err());
+    assert!("Intro, , Outro".parse::<Form>().is_err());
+}
+
+#[test]
+fn test_form_with_unknown_section_warns() {
+    let input = "@form: Intro Verse Coda\n#Intro\n| [C] |\n#Verse\n[C] Hello";
+    let (_, diagnostics) = parse_song_with_diagnostics(input).unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code.as_deref(), Some("W011"));
+    assert_eq!(diagnostics[0].span.unwrap().line_col(input), (1, 1));
+}
+
+fn song_chords_and_markers(song: &Song) -> Vec<ChordOrText> {
+    song.blocks.iter().flat_map(|b| &b.lines).flat_map(|l| &l.segments).flat_map(|s| s.items().to_vec()).collect()
+}
+
+fn measure_chords(block: &Block) -> Vec<String> {
+    block
+        .lines
+        .iter()
+        .flat_map(|line| &line.segments)
+        .map(|segment| {
+            segment
+                .items()
+                .iter()
+                .filter_map(|item| match item {
+                    ChordOrText::Chord(c) => Some(format!("{:?}", c.root.letter)),
+                    _ => None,
+                })
+                .collect::<Vec<String>>()
+                .join(" ")
+        })
+        .collect()
+}
+
+#[test]
+fn test_expand_form_resolves_repeats() {
+    use leadsheetml::form::expand_form;
+
+    let input = "@form: Intro Verse x2 Outro\n#Intro\n| [C] |\n#Verse\n|: [D] |1. [E] :|2. [F] |\n| [G] :| x3\n#Outro\n| [A] |]";
+    let song = parse_song_from_str(input).unwrap();
+    let expanded = expand_form(&song);
+    let names: Vec<&str> = expanded.blocks.iter().map(|b| b.name()).collect();
+    assert_eq!(names, vec!["Intro", "Verse", "Verse", "Outro"]);
+    assert_eq!(measure_chords(&expanded.blocks[1]), vec!["D", "E", "D", "F", "G", "G", "G"]);
+
+    let measures = song_measures(&expanded);
+    assert!(measures.iter().all(|m| !m.start.starts_repeat() && m.volta.is_none() && m.repeat_count.is_none()));
+    assert_eq!(measures.last().unwrap().end, Some(Barline::Final));
+
+    let song = parse_song_from_str("#A\n|: [C] |1. [D] :|2. [E] :|3. [F] |]").unwrap();
+    assert_eq!(measure_chords(&expand_form(&song).blocks[0]), vec!["C", "D", "C", "E", "C", "F"]);
+}
+
+#[test]
+fn test_expand_form_follows_road_map() {
+    use leadsheetml::form::expand_form;
+
+    let input = "#Intro\n| [C] |\n#Head\n| {Segno} [D] |: [E] |1. [F] :|2. [G] {To Coda} |\n| [A] {D.S. al Coda} |\n#Coda\n| {Coda} [B] |]";
+    let played = expand_form(&parse_song_from_str(input).unwrap());
+    let names: Vec<&str> = played.blocks.iter().map(|b| b.name()).collect();
+    assert_eq!(names, vec!["Intro", "Head", "Head", "Coda"]);
+    assert_eq!(measure_chords(&played.blocks[1]), vec!["D", "E", "F", "E", "G", "A"]);
+    assert_eq!(measure_chords(&played.blocks[2]), vec!["D", "E", "G"]);
+    assert_eq!(measure_chords(&played.blocks[3]), vec!["B"]);
+    assert!(song_chords_and_markers(&played).iter().all(|item| !matches!(item, ChordOrText::Marker(..))));
+
+    let played = expand_form(&parse_song_from_str("#A\n| [C] | [D] {Fine} |\n#B\n| [E] {D.C. al Fine} |").unwrap());
+    let chords: Vec<Vec<String>> = played.blocks.iter().map(measure_chords).collect();
+    assert_eq!(chords, vec![vec!["C", "D"], vec!["E"], vec!["C", "D"]]);
+
+    let played = expand_form(&parse_song_from_str("#A\n|: [C] | [G] :|\n{Segno} [C] la {D.S. al Fine}").unwrap());
+    assert_eq!(played.blocks.len(), 2);
+    assert_eq!(played.blocks[1].lines.len(), 1);
+    assert!(matches!(&played.blocks[1].lines[0].segments[0], Segment::Inline(..)));
+
+    // Markers in the middle of a lyric line take effect where they are.
+    let input = "#A\n{Segno} [D]a {To Coda} [G]b {D.S. al Coda}\n#Coda\n{Coda} [F]c";
+    let played = expand_form(&parse_song_from_str(input).unwrap());
+    let chords: Vec<String> = played.blocks.iter().map(|b| measure_chords(b).join(" ")).collect();
+    assert_eq!(chords, vec!["D G", "D", "F"]);
+    let played = expand_form(&parse_song_from_str("#A\n[C]a {Fine} [G]b {D.C. al Fine}").unwrap());
+    let chords: Vec<String> = played.blocks.iter().map(|b| measure_chords(b).join(" ")).collect();
+    assert_eq!(chords, vec!["C G", "C"]);
+}
+
+#[test]
+fn test_render_form_summary() {
+    let song = parse_song_from_str("@title: Test\n@form: Intro V C x2\n#Intro\n| [C] |\n#V\n[C] Hi\n#C\n[F] Ho").unwrap();
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &song);
+    assert!(md.contains("- Form: Intro → V → C x2\n"));
+}
+
+#[test]
+fn test_section_reference_reuses_block() {
+    let song = parse_song_from_str("#Chorus\n[F] Ho [C] hey\n#Verse\n[G] Hi\n#Chorus*").unwrap();
+    assert_eq!(song.blocks.len(), 3);
+    assert!(!song.blocks[0].is_reference);
+    assert!(song.blocks[2].is_reference);
+    assert_eq!(song.blocks[2].name(), "Chorus");
+    assert_eq!(measure_chords(&song.blocks[2]), measure_chords(&song.blocks[0]));
+
+    let input = "#Verse\n[G] Hi\n#Chorus*";
+    let error = parse_song_from_str(input).unwrap_err();
+    let diagnostic = leadsheetml::report::error_to_diagnostic(&error, input);
+    assert_eq!(diagnostic.code.as_deref(), Some("E0004"));
+    assert!(diagnostic.message.contains("Chorus*"));
+
+    assert!(parse_song_from_str("#Chorus\n[F] Ho\n#Chorus*\n[C] Hey").is_err());
+}
+
+#[test]
+fn test_render_section_reference() {
+    let song = parse_song_from_str("#Chorus\n[F] Ho [C] hey\n#Chorus*").unwrap();
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &song);
+    assert_eq!(md.matches("Ho").count(), 1);
+    assert!(md.contains("*Repeat Chorus*"));
+
+    let options = RenderOptions { full_references: true, ..RenderOptions::default() };
+    let md = DefaultLeadSheetRenderer.render_song_with_options(&MarkdownEngine, &song, &options);
+    assert_eq!(md.matches("Ho").count(), 2);
+    assert!(!md.contains("Repeat Chorus"));
+}
+
+#[test]
+fn test_section_names_take_the_whole_line() {
+    let input = "@form: Intro, Estribillo, Pre-Chorus\n#Verse 2\n[C] Hi\n#  Pre-Chorus   // build up\n[G] Ho\n#Intro (x2)\n| [C] |\n#Estribillo ×3\n[Am] Canção, coração\n#Ça va*";
+    let error = parse_song_from_str(input).unwrap_err();
+    assert!(error.to_string().contains("#Ça va*"));
+
+    let input = input.replace("#Ça va*", "#Verse 2*");
+    let (song, diagnostics) = parse_song_with_diagnostics(&input).unwrap();
+    assert!(diagnostics.is_empty());
+    let names: Vec<&str> = song.blocks.iter().map(|b| b.name()).collect();
+    assert_eq!(names, vec!["Verse 2", "Pre-Chorus", "Intro (x2)", "Estribillo ×3", "Verse 2"]);
+    assert_eq!(song.blocks[0].section, SectionName { label: "Verse".to_string(), number: Some(2), repeat_count: None });
+    assert_eq!(song.blocks[2].section, SectionName { label: "Intro".to_string(), number: None, repeat_count: Some(2) });
+    assert_eq!(song.blocks[3].section.repeat_count, Some(3));
+    assert!(song.blocks[4].is_reference);
+    assert!(song.blocks[3].is_named("estribillo"));
+
+    assert_eq!(parse_section_name("Solo 2 x4"), SectionName { label: "Solo".to_string(), number: Some(2), repeat_count: Some(4) });
+    assert_eq!(parse_section_name("2").label, "2");
+    assert_eq!(parse_section_name("x2").repeat_count, None);
+
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &song);
+    assert!(md.contains("### Estribillo ×3\n"));
+
+    let song = parse_song_from_str("#A\n[C] Hi").unwrap();
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &song);
+    assert!(md.starts_with("### A\n```"));
+
+    let song = parse_song_from_str("#Intro x2\n| [C] |\n#Verse\n[D] Hi").unwrap();
+    let names: Vec<String> = leadsheetml::form::expand_form(&song).blocks.iter().map(|b| b.section.title()).collect();
+    assert_eq!(names, vec!["Intro", "Intro", "Verse"]);
+}
+
+#[test]
+fn test_render_aligns_multilingual_lyrics() {
+    let input = "#Verso\n[C]Canção do [G]coração\n#Decomposed\n[C]Cancao\u{303} do [G]corac\u{327}a\u{303}o\n#歌\n[C]日本の[G]歌[Am]です\n#Emoji\n[C]👍🏽 ok [G]fin";
+    let song = parse_song_from_str(input).unwrap();
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &song);
+    assert!(md.contains("**C             G          **\nCanção do     coração\n"));
+    assert!(md.contains("**C             G          **\nCancao\u{303} do     corac\u{327}a\u{303}o\n"));
+    assert!(md.contains("**C         G     Am      **\n日本の    歌    です\n"));
+    assert!(md.contains("**C         G      **\n👍🏽 ok     fin\n"));
+
+    let html = DefaultLeadSheetRenderer.render_song(&HtmlEngine, &song);
+    assert!(html.contains("<b>C         G     Am      </b>\n日本の    歌    です"));
+}
+
+fn parse_chord_symbol(symbol: &str) -> Chord {
+    let parsed = LeadSheetMLParser::parse(Rule::chord_token, symbol).unwrap();
+    parse_chord_token(parsed.into_iter().next().unwrap(), &mut Warnings::default()).unwrap()
+}
+
+#[test]
+fn test_typed_chord_model() {
+    let chord = parse_chord_symbol("[Bbm7b5]");
+    assert_eq!(chord.quality, Quality::HalfDiminished);
+    assert_eq!(chord.extension, Some(Extension { degree: 7, major_seventh: false }));
+    assert!(chord.alterations.is_empty());
+
+    let chord = parse_chord_symbol("[Cmaj9#11/E]");
+    assert_eq!(chord.quality, Quality::Major);
+    assert_eq!(chord.extension, Some(Extension { degree: 9, major_seventh: true }));
+    assert_eq!(chord.alterations, vec![Alteration::Sharp(11)]);
+    assert_eq!(chord.bass.unwrap().letter, NoteLetter::E);
+
+    assert_eq!(parse_chord_symbol("[G7sus4]").quality, Quality::Suspended(4));
+    assert_eq!(parse_chord_symbol("[A5]").quality, Quality::Power);
+    assert_eq!(parse_chord_symbol("[Cm6]").alterations, vec![Alteration::Sixth]);
+    assert_eq!(parse_chord_symbol("[C+]").quality, Quality::Augmented);
+    assert_eq!(parse_chord_symbol("[Cmaj]"), parse_chord_symbol("[C]"));
+    assert_eq!(parse_chord_symbol("[Cmin7]"), parse_chord_symbol("[Cm7]"));
+}
+
+#[test]
+fn test_chords_format_canonically() {
+    let cases = [
+        ("[C]", "C"),
+        ("[Cmaj]", "C"),
+        ("[Cmin7]", "Cm7"),
+        ("[C13]", "C13"),
+        ("[C+]", "Caug"),
+        ("[Ebdim7]", "Ebdim7"),
+        ("[Amb57]", "Am7b5"),
+        ("[F#m7b5/C]", "F#m7b5/C"),
+        ("[D7sus4]", "D7sus4"),
+        ("[Cmaj7sus2]", "Cmaj7sus2"),
+        ("[G7b9#11]", "G7b9#11"),
+        ("[E5]", "E5"),
+    ];
+    for (symbol, canonical) in cases {
+        let chord = parse_chord_symbol(symbol);
+        assert_eq!(chord.to_string(), canonical, "{}", symbol);
+        assert_eq!(parse_chord_symbol(&format!("[{}]", canonical)), chord, "{}", canonical);
+    }
+}
+
+/// Chord symbols as they appear in real jazz and pop charts, with the
+/// canonical spelling each one normalizes to.
+const CHORD_CORPUS: &[(&str, &str)] = &[
+    // Major sevenths
+    ("Cmaj7", "Cmaj7"), ("CΔ7", "Cmaj7"), ("CΔ", "Cmaj7"), ("C^7", "Cmaj7"), ("CM7", "Cmaj7"),
+    ("CMaj9", "Cmaj9"), ("Ebmaj13", "Ebmaj13"), ("Fmaj7#11", "Fmaj7#11"), ("BbΔ9", "Bbmaj9"),
+    // Minor
+    ("C-", "Cm"), ("C-7", "Cm7"), ("Cmi7", "Cm7"), ("Cmin9", "Cm9"), ("Dm11", "Dm11"), ("F#m", "F#m"),
+    ("CmMaj7", "CmMaj7"), ("Cm(maj7)", "CmMaj7"), ("C-Δ7", "CmMaj7"), ("Cm6", "Cm6"), ("Cm69", "Cm6/9"),
+    // Half-diminished and diminished
+    ("Cm7b5", "Cm7b5"), ("Cø", "Cm7b5"), ("Cø7", "Cm7b5"), ("C-7b5", "Cm7b5"), ("Dm7(b5)", "Dm7b5"),
+    ("F#ø7", "F#m7b5"), ("Amb57", "Am7b5"), ("C°", "Cdim"), ("C°7", "Cdim7"), ("Co7", "Cdim7"), ("Abdim7", "Abdim7"),
+    // Dominants and tensions
+    ("G7", "G7"), ("G9", "G9"), ("G13", "G13"), ("G7alt", "G7alt"), ("G7b9", "G7b9"), ("G7(b9)", "G7b9"),
+    ("G7(b9,#11)", "G7b9#11"), ("G7(#9 b13)", "G7#9b13"), ("G13(b9)", "G13b9"), ("C13#11", "C13#11"),
+    ("C7#5", "C7#5"), ("C+7", "Caug7"), ("C+", "Caug"), ("C+Δ7", "CaugMaj7"),
+    // Suspended, power and added tones
+    ("Csus", "Csus4"), ("C7sus", "C7sus4"), ("Dsus4", "Dsus4"), ("Esus2", "Esus2"), ("C9sus4", "C9sus4"),
+    ("E5", "E5"), ("Cadd9", "Cadd9"), ("Cmadd9", "Cmadd9"), ("D2", "Dadd2"), ("C6", "C6"), ("C6/9", "C6/9"),
+    ("C69", "C6/9"), ("C(no3)", "Comit3"), ("C7omit5", "C7omit5"),
+    // Slash chords
+    ("G/B", "G/B"), ("D/F#", "D/F#"), ("Am7/G", "Am7/G"), ("C6/9/E", "C6/9/E"), ("Bbmaj7/D", "Bbmaj7/D"),
+];
+
+#[test]
+fn test_chord_corpus_normalizes() {
+    for (symbol, canonical) in CHORD_CORPUS {
+        let input = format!("#Chart\n[{}] la", symbol);
+        let (song, diagnostics) = parse_song_with_diagnostics(&input)
+            .unwrap_or_else(|e| panic!("{} did not parse: {}", symbol, e));
+        assert!(diagnostics.is_empty(), "{} warned: {:?}", symbol, diagnostics);
+        let chord = &song_chords(&song)[0];
+        assert_eq!(chord.to_string(), *canonical, "{}", symbol);
+        assert_eq!(&parse_chord_symbol(&format!("[{}]", canonical)), chord, "{} -> {}", symbol, canonical);
+    }
+}
+
+#[test]
+fn test_parse_chart_symbols() {
+    let song = parse_song_from_str("#Intro\n| N.C. | % | [Cm7] / / / | [F7] [rest] [/] 𝄽 |\n#Verse\n[N.C.] The [nc]falling and/or 100% sure").unwrap();
+    let kinds: Vec<&str> = song.blocks[0].lines[0].segments.iter()
+        .flat_map(|segment| segment.items())
+        .map(|item| match item {
+            ChordOrText::NoChord(_) => "N.C.",
+            ChordOrText::RepeatMeasure(_) => "%",
+            ChordOrText::Slash(_) => "/",
+            ChordOrText::Rest(_) => "rest",
+            ChordOrText::Chord(_) => "chord",
+            _ => "other",
+        })
+        .collect();
+    assert_eq!(kinds, vec!["N.C.", "%", "chord", "/", "/", "/", "chord", "rest", "/", "other"]);
+
+    let verse = song.blocks[1].lines[0].segments[0].items();
+    assert!(matches!(verse[0], ChordOrText::NoChord(_)));
+    assert!(matches!(verse[2], ChordOrText::NoChord(_)));
+    assert!(matches!(&verse[3], ChordOrText::Text(text, _) if text == "falling and/or 100% sure"));
+
+    let (_, diagnostics) = parse_song_with_diagnostics("#Intro\n| [C] / / %").unwrap();
+    assert_eq!(diagnostics[0].code.as_deref(), Some("W005"));
+}
+
+#[test]
+fn test_chart_symbols_survive_transpose_and_render() {
+    let song = parse_song_from_str("@key: C\n#Intro\n| N.C. | [C] / / / | % | [G] [rest] |").unwrap();
+    let (transposed, _) = transpose_song(song, 2);
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &transposed);
+    assert!(md.contains("**| N.C. | D   /   /   /   | %   | A   rest |**"));
+
+    let html = DefaultLeadSheetRenderer.render_song_with_options(&HtmlEngine, &transposed, &RenderOptions {
+        music_symbols: true,
+        ..RenderOptions::default()
+    });
+    assert!(html.contains("A   \u{1D13D}   |"));
+
+    let song = parse_song_from_str("#A\n| [N.C.][C] [rest][G] |").unwrap();
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &song);
+    assert!(md.contains("**| N.C. C   rest G   |**"));
+}
+
+#[test]
+fn test_measure_beat_positions() {
+    use leadsheetml::metadata::TimeSignature;
+
+    let song = parse_song_from_str("#A\n| [C]..[G].. | [C:3] [G] | [Am] / / [D] | [C] [G] [F] | [C] | % |").unwrap();
+    let measures = song_measures(&song);
+    let four = TimeSignature { beats: 4, unit: 4 };
+    let beats = |measure: &Measure| -> Vec<(usize, u32, u32)> {
+        measure.beats(&four).iter().map(|p| (p.index, p.beat, p.length)).collect()
+    };
+    assert_eq!(beats(&measures[0]), vec![(0, 1, 2), (1, 3, 2)]);
+    assert_eq!(beats(&measures[1]), vec![(0, 1, 3), (1, 4, 1)]);
+    assert_eq!(beats(&measures[2]), vec![(0, 1, 3), (3, 4, 1)]);
+    assert_eq!(beats(&measures[3]), vec![(0, 1, 2), (1, 3, 1), (2, 4, 1)]);
+    assert_eq!(beats(&measures[4]), vec![(0, 1, 4)]);
+    assert_eq!(beats(&measures[5]), vec![(0, 1, 4)]);
+    assert!(measures[0].has_written_beats());
+    assert!(!measures[3].has_written_beats());
+
+    let six = TimeSignature { beats: 6, unit: 8 };
+    assert_eq!(measures[0].beats(&six)[1].beat, 3);
+    assert_eq!(measures[3].beats(&six).iter().map(|p| p.length).collect::<Vec<u32>>(), vec![2, 2, 2]);
+
+    assert!(parse_song_from_str("#A\n| .. [C] |").is_err());
+    assert!(parse_song_from_str("#A\n| [C:0] |").is_err());
+    assert!(parse_song_from_str("#A\n| [C:4294967295] [G:2] |").is_err());
+    assert!(parse_song_from_str("#A\n| [C:64] |").is_ok());
+
+    // Sums stay in range even for a time signature the parser would refuse.
+    let huge = TimeSignature { beats: u32::MAX, unit: 4 };
+    assert_eq!(measures[3].beats(&huge).len(), 3);
+    let (_, diagnostics) = parse_song_with_diagnostics("@time: 4294967295/4\n#A\n| [C] |").unwrap();
+    let codes: Vec<&str> = diagnostics.iter().filter_map(|d| d.code.as_deref()).collect();
+    assert_eq!(codes, vec!["W008"]);
+    let lyrics = parse_song_from_str("#A\n[C]... and then").unwrap();
+    assert!(matches!(&lyrics.blocks[0].lines[0].segments[0].items()[1], ChordOrText::Text(text, _) if text == "... and then"));
+}
+
+#[test]
+fn test_beat_mismatch_warns() {
+    let input = "@time: 3/4\n#A\n| [C]..[G]. | [C:2] [G:2] | [Am] / / / | [C] [G] [F] [E] | [C] [G] |";
+    let (_, diagnostics) = parse_song_with_diagnostics(input).unwrap();
+    let codes: Vec<&str> = diagnostics.iter().filter_map(|d| d.code.as_deref()).collect();
+    assert_eq!(codes, vec!["W012", "W012", "W012"]);
+    assert_eq!(diagnostics[0].message, "Measure has 4 beats, but 3/4 time has 3");
+    assert_eq!(diagnostics[2].message, "Measure has 4 chords, more than the 3 beats of 3/4 time");
+    assert_eq!(diagnostics[0].span.unwrap().line_col(input), (3, 13));
+
+    let (_, diagnostics) = parse_song_with_diagnostics("#A\n| [C] / / [G] | [C:2] [G:2] |").unwrap();
+    assert!(diagnostics.is_empty());
+}
+
+#[test]
+fn test_parse_inline_directives() {
+    let input = "@key: C\n#Verse\n[C] Hello\n@time: 3/4\n| [F] [G] [C] |\n@time: 4/4\n#Chorus\n[F] Ho\n@key: D\n#Chorus*\n@tempo: 140";
+    let (song, diagnostics) = parse_song_with_diagnostics(input).unwrap();
+    assert!(diagnostics.is_empty());
+    assert_eq!(song.directives.len(), 1);
+
+    let verse = &song.blocks[0];
+    assert_eq!(verse.lines.len(), 2);
+    let changes: Vec<(usize, &str, &str)> = verse.directives.iter()
+        .map(|d| (d.line, d.directive.name.as_str(), d.directive.value.as_str()))
+        .collect();
+    assert_eq!(changes, vec![(1, "time", "3/4"), (2, "time", "4/4")]);
+    assert!(matches!(&verse.lines[0].segments[0].items()[1], ChordOrText::Text(text, _) if text.trim() == "Hello"));
+    assert_eq!(verse.directives_at(1).map(|d| d.value.as_str()).collect::<Vec<&str>>(), vec!["3/4"]);
+
+    // A reference copies the lines and the directives between them; its own
+    // come after them.
+    let reference = &song.blocks[2];
+    let changes: Vec<(usize, &str)> = reference.directives.iter().map(|d| (d.line, d.directive.name.as_str())).collect();
+    assert_eq!(changes, vec![(1, "tempo")]);
+
+    let (song, diagnostics) = parse_song_with_diagnostics("#A\n[C] Hi\n@title: Other\n@key: Q").unwrap();
+    let codes: Vec<&str> = diagnostics.iter().filter_map(|d| d.code.as_deref()).collect();
+    assert_eq!(codes, vec!["W013", "W008"]);
+    assert_eq!(song.blocks[0].directives.len(), 1);
+}
+
+#[test]
+fn test_transpose_follows_key_changes() {
+    let song = parse_song_from_str("@key: C Major\n#Verse\n[C] Hi [F#m] there\n@key: Db Major\n[Db] Up [Gb] we go").unwrap();
+
+    let (transposed, _) = transpose_song(song.clone(), 2);
+    assert_eq!(transposed.blocks[0].directives[0].directive.value, "Eb Major");
+    let roots: Vec<String> = song_chords(&transposed).iter().map(|c| c.to_string()).collect();
+    assert_eq!(roots, vec!["D", "G#m", "Eb", "Ab"]);
+
+    let transposed = transpose_to_key(song, "A").unwrap();
+    assert_eq!(transposed.blocks[0].directives[0].directive.value, "Bb Major");
+    let roots: Vec<String> = song_chords(&transposed).iter().map(|c| c.to_string()).collect();
+    assert_eq!(roots, vec!["A", "D#m", "Bb", "Eb"]);
+}
+
+#[test]
+fn test_time_changes_render_and_set_beats() {
+    let input = "@time: 4/4\n#A\n| [C] [G] |\n@time: 3/4\n| [C:2] [G] |\n@time: 4/4\n| [C:2] [G] |";
+    let (song, diagnostics) = parse_song_with_diagnostics(input).unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "Measure has 3 beats, but 4/4 time has 4");
+
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &song);
+    let rows: Vec<&str> = md.lines().filter(|line| line.starts_with('*')).collect();
+    assert_eq!(rows, vec!["**| C   G   |**", "*Time: 3/4*", "**| C   G   |**", "*Time: 4/4*", "**| C   G   |**"]);
+
+    let options = RenderOptions { capo: Some(2), ..RenderOptions::default() };
+    let song = parse_song_from_str("@key: C Major\n#A\n[C] Hi\n@key: D Major\n[D] Ho").unwrap();
+    let html = DefaultLeadSheetRenderer.render_song_with_options(&HtmlEngine, &song, &options);
+    assert!(html.contains("<i>Key: D Major (C Major shapes)</i>"));
+}
+
+#[test]
+fn test_parse_pickups() {
+    use leadsheetml::metadata::TimeSignature;
+
+    let input = "@time: 3/4\n#Intro\n| {Pickup} [G] | [C] [F] [G] |\n| {anacrusis} [C:2] [G:2] |\n#Verse\n{Pickup} And [C]soon I'll [G] go";
+    let (song, diagnostics) = parse_song_with_diagnostics(input).unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "Measure has 4 beats, but 3/4 time has 3");
+
+    let measures = song_measures(&song);
+    assert!(measures[0].pickup);
+    assert!(!measures[1].pickup);
+    assert_eq!(measures[0].items.len(), 1);
+    let three = TimeSignature { beats: 3, unit: 4 };
+    assert_eq!(measures[0].beats(&three), vec![BeatPosition { index: 0, beat: 3, length: 1 }]);
+
+    let verse = &song.blocks[1].lines[0];
+    assert!(verse.pickup);
+    assert!(!song.blocks[0].lines[0].pickup);
+    assert!(matches!(&verse.segments[0].items()[0], ChordOrText::Text(text, _) if text.trim() == "And"));
+
+    let (transposed, _) = transpose_song(song.clone(), 2);
+    assert!(song_measures(&transposed)[0].pickup);
+    assert!(transposed.blocks[1].lines[0].pickup);
+    assert!(leadsheetml::form::expand_form(&song).blocks[1].lines[0].pickup);
+
+    assert!(parse_song_from_str("#A\n| [C] {Pickup} [G] |").is_err());
+}
+
+#[test]
+fn test_measure_numbers_skip_pickups() {
+    let input = "#Intro\n| {Pickup} [G] | [C] [Am] | [F] [G] |\n#Head\n|: [C] [Am] | [F] [G] |\n|1. [Dm] | [G] :|2. [G7] | [C] |]\n#Verse\n[C] Hi\n#Head*";
+    let song = parse_song_from_str(input).unwrap();
+    let numbered = song.numbered_measures();
+    assert_eq!(numbered.len(), 14);
+    assert_eq!(numbered[0].number, 1);
+    assert_eq!((numbered[0].block, numbered[0].line, numbered[0].segment), (0, 0, 1));
+
+    let bar = song.measure_at(5).unwrap();
+    assert_eq!((bar.block, bar.line, bar.segment), (1, 1, 0));
+    assert_eq!(bar.measure.volta.as_ref().map(Volta::label).as_deref(), Some("1."));
+    assert_eq!(song.measure_at(9).unwrap().block, 3);
+    assert!(song.measure_at(15).is_none());
+    assert!(song.measure_at(0).is_none());
+
+    // Written out, the repeat and both endings are counted as played.
+    let played = leadsheetml::form::expand_form(&song);
+    assert_eq!(played.numbered_measures().len(), 18);
+    assert!(matches!(&played.measure_at(5).unwrap().measure.items[0], ChordOrText::Chord(c) if c.to_string() == "Dm"));
+    assert!(matches!(&played.measure_at(9).unwrap().measure.items[0], ChordOrText::Chord(c) if c.to_string() == "G7"));
+
+    // A row that opens with a pickup is numbered from its first full bar.
+    let options = RenderOptions { measure_numbers: true, ..RenderOptions::default() };
+    let md = DefaultLeadSheetRenderer.render_song_with_options(&MarkdownEngine, &song, &options);
+    assert!(md.contains("**| G   | 1 C   Am  | F   G   |**"));
+}
+
+#[test]
+fn test_rehearsal_marks() {
+    let song = parse_song_from_str("#Intro\n| [C] |\n#Verse\n@mark: F\n[C] Hi\n#Chorus\n@mark: Chorus 1\n| [F] |\n#Bridge\n| [G] |\n#Verse*").unwrap();
+    assert_eq!(song.blocks[1].mark(), Some("F"));
+    assert_eq!(song.blocks[4].mark(), None);
+    assert_eq!(song.rehearsal_marks(), vec!["A", "F", "Chorus 1", "G", "H"]);
+
+    let md = DefaultLeadSheetRenderer.render_song(&MarkdownEngine, &song);
+    assert!(md.contains("### [F] Verse"));
+    assert!(md.contains("### Intro"));
+    assert!(!md.contains("Mark"));
+
+    let options = RenderOptions { rehearsal_marks: true, measure_numbers: true, ..RenderOptions::default() };
+    let md = DefaultLeadSheetRenderer.render_song_with_options(&MarkdownEngine, &song, &options);
+    assert!(md.contains("### [A] Intro"));
+    assert!(md.contains("**1 | C   |**"));
+    assert!(md.contains("**3 | G   |**"));
+    assert!(md.contains("*Repeat Verse*"));
+    let html = DefaultLeadSheetRenderer.render_song_with_options(&HtmlEngine, &song, &options);
+    assert!(html.contains("<h3>[Chorus 1] Chorus</h3>"));
+    assert!(html.contains("<b>2 | F   |</b>"));
+}
+
+#[test]
+fn test_grid_renderer_markdown() {
+    let song = parse_song_from_str("#Intro\n| {Pickup} [G] | [C] [Am] | [F] [G] |\n#Head\n|: [C] [Am] | [F] [G] :| x3\n|1. [Dm] | [G] || [Cmaj7] |]\n#Verse\n[C] Hello [G] there").unwrap();
+    let md = GridLeadSheetRenderer::default().render_song(&MarkdownEngine, &song);
+    let rows: Vec<&str> = md.lines().filter(|line| line.starts_with('|')).collect();
+    assert_eq!(rows, vec![
+        "|             |            |            |            |            |",
+        "| ----------: | ---------- | ---------- | ---------- | ---------- |",
+        "| **Intro** G | C Am       | F G        |            |            |",
+        "|    **Head** | \\|: C Am   | F G :\\| x3 | 1. Dm      | G \\|\\|     |",
+        "|             | Cmaj7 \\|]  |            |            |            |",
+    ]);
+    // Sections with lyrics keep the chords-over-lyrics layout.
+    assert!(md.contains("### Verse\n```\n**C         G        **\nHello     there\n```"));
+    assert!(!md.contains("### Intro"));
+
+    let renderer = GridLeadSheetRenderer { bars_per_row: 2, ..GridLeadSheetRenderer::default() };
+    let md = renderer.render_song(&MarkdownEngine, &song);
+    assert_eq!(md.lines().filter(|line| line.starts_with('|')).count(), 6);
+}
+
+#[test]
+fn test_grid_renderer_html() {
+    let song = parse_song_from_str("@time: 4/4\n#A\n| [C] | [Am7] | [F] | [G7] | [C] |\n@time: 3/4\n| [D] [E] [F] |\n#B\n| {Segno} [F] |]").unwrap();
+    let options = RenderOptions { measure_numbers: true, rehearsal_marks: true, music_symbols: true, ..RenderOptions::default() };
+    assert_eq!(TableFormat::of(&HtmlEngine), TableFormat::Html);
+    assert_eq!(TableFormat::of(&MarkdownEngine), TableFormat::Markdown);
+    let html = GridLeadSheetRenderer::default().render_song_with_options(&HtmlEngine, &song, &options);
+    assert!(!html.contains("| ---"));
+    let renderer = GridLeadSheetRenderer { format: Some(TableFormat::Markdown), ..GridLeadSheetRenderer::default() };
+    assert!(renderer.render_song_with_options(&HtmlEngine, &song, &options).contains("| ---"));
+    assert_eq!(html.matches("<table").count(), 2);
+    assert_eq!(html.matches("<col style=\"width: 10ch\">").count(), 8);
+    assert!(html.contains("<tr><th scope=\"row\" style=\"text-align: right\"><b>[A] A</b></th><td>1 C</td><td>Am7</td><td>F</td><td>G7</td></tr>"));
+    assert!(html.contains("<td>5 C</td><td></td>"));
+    assert!(html.contains("</table>\n<br/><i>Time: 3/4</i>"));
+    assert!(html.contains("<b>[B] B</b></th><td>7 \u{1D10B} F |]</td>"));
+}